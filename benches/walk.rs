@@ -0,0 +1,81 @@
+//! Compares how long the serial and `--parallel-walk` directory walks take
+//! on a large generated tree, as reported by `--timings`. Run with
+//! `cargo bench --bench walk`, optionally followed by `-- <DIRS> <FILES>` for
+//! the number of directories and of files in each.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+const RUNS: usize = 5;
+
+fn main() {
+    let mut sizes = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| arg.parse::<usize>().expect("sizes must be numbers"));
+    let dirs = sizes.next().unwrap_or(500);
+    let files_per_dir = sizes.next().unwrap_or(40);
+
+    let root = std::env::temp_dir().join(format!("gprepo-bench-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    git2::Repository::init(&root).unwrap();
+    for dir in 0..dirs {
+        let dir_path = root.join(format!("d{}/sub{}", dir % 20, dir));
+        fs::create_dir_all(&dir_path).unwrap();
+        for file in 0..files_per_dir {
+            fs::write(dir_path.join(format!("f{}.txt", file)), "x\n").unwrap();
+        }
+    }
+    println!("{} files in {} directories", dirs * files_per_dir, dirs);
+
+    for (label, args) in [("serial", &[][..]), ("parallel", &["--parallel-walk"][..])] {
+        let mut walks: Vec<Duration> = (0..RUNS).map(|_| walk_time(&root, args)).collect();
+        walks.sort();
+        println!(
+            "{:<10}median {:.3?}, fastest {:.3?}",
+            label,
+            walks[RUNS / 2],
+            walks[0]
+        );
+    }
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// Runs gprepo over `root` and returns the walking time from `--timings`.
+/// Files written just before the run are skipped as modified during it, so
+/// the rest of the run stays short.
+fn walk_time(root: &Path, args: &[&str]) -> Duration {
+    let output = Command::new(env!("CARGO_BIN_EXE_gprepo"))
+        .args(["--timings", "-o", "-"])
+        .args(args)
+        .current_dir(root)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let walking = stderr
+        .lines()
+        .find_map(|line| line.trim_start().strip_prefix("walking"))
+        .expect("no walking time in --timings output")
+        .trim();
+    parse_debug_duration(walking)
+}
+
+/// Parses a duration printed with `{:.3?}`, such as `12.345ms`.
+fn parse_debug_duration(text: &str) -> Duration {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (amount, unit) = text.split_at(split);
+    let amount: f64 = amount.parse().unwrap();
+    let seconds = match unit {
+        "ns" => amount / 1e9,
+        "µs" => amount / 1e6,
+        "ms" => amount / 1e3,
+        "s" => amount,
+        _ => panic!("unknown duration unit in {}", text),
+    };
+    Duration::from_secs_f64(seconds)
+}
@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use git2::Repository;
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A `.tar.gz` archive unpacked into a temporary directory, which is removed
+/// again when this is dropped.
+pub struct ExtractedArchive {
+    dir: PathBuf,
+}
+
+impl ExtractedArchive {
+    /// Unpacks the archive and initializes an empty repository in it, so that
+    /// any `.gitignore` files it contains apply just as in a checkout.
+    pub fn extract(archive_path: &Path) -> Result<Self> {
+        let extracted = ExtractedArchive {
+            dir: create_unique_dir()?,
+        };
+
+        let file = File::open(archive_path)
+            .with_context(|| format!("Could not open archive {}", archive_path.display()))?;
+        tar::Archive::new(GzDecoder::new(file))
+            .unpack(&extracted.dir)
+            .with_context(|| format!("Could not extract archive {}", archive_path.display()))?;
+        Repository::init(&extracted.dir).context("Could not initialize extracted archive")?;
+        Ok(extracted)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// Creates a directory with a random name in the system temporary directory.
+/// A name that is already taken is never reused, so another process's
+/// directory is neither cleared nor shared.
+fn create_unique_dir() -> Result<PathBuf> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    for _ in 0..16 {
+        let suffix = RandomState::new().build_hasher().finish();
+        let dir = std::env::temp_dir().join(format!("gprepo-archive-{:016x}", suffix));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).context("Could not create a temporary directory"),
+        }
+    }
+    anyhow::bail!("Could not find an unused temporary directory name")
+}
+
+impl Drop for ExtractedArchive {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_dirs_are_fresh_and_private() {
+        let first = create_unique_dir().unwrap();
+        let second = create_unique_dir().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fs::read_dir(&first).unwrap().count(), 0);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&first).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        fs::remove_dir(first).unwrap();
+        fs::remove_dir(second).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Component, Path};
+
+pub const END_MARKER: &str = "@@@@END@@@@";
+
+pub struct BundledFile {
+    pub path: String,
+    pub contents: String,
+}
+
+/// Returns the path named by a `@@@@<file-path>@@@@` header line.
+fn header_path(line: &str) -> Option<&str> {
+    line.strip_prefix("@@@@")?
+        .strip_suffix("@@@@")
+        .filter(|path| !path.is_empty())
+}
+
+/// Whether a line would be read as a file header or the END marker.
+pub fn is_marker_line(line: &str) -> bool {
+    line == END_MARKER || header_path(line).is_some()
+}
+
+/// Returns the file name of a `--- <name> ---` line, which starts each file
+/// within a block combined by `--merge-under`.
+fn merged_file_name(line: &str) -> Option<&str> {
+    line.strip_prefix("--- ")?
+        .strip_suffix(" ---")
+        .filter(|name| !name.is_empty())
+}
+
+/// Whether a line would start a new file within a `--merge-under` block.
+pub fn is_merged_file_line(line: &str) -> bool {
+    merged_file_name(line).is_some()
+}
+
+/// Splits a delimited bundle back into its files. Text before the first
+/// header is the preamble, followed by any `Note:` lines about the run, and
+/// text after the END marker holds instructions, so both are skipped. The
+/// newline written after each file's contents is removed again. Directory
+/// headers from `--compact-headers` are applied to the file headers that
+/// follow them, and `<dir>/*` blocks from `--merge-under` are split at their
+/// `--- <name> ---` lines.
+pub fn parse(bundle: &str) -> Result<Vec<BundledFile>> {
+    let mut files = Vec::new();
+    let mut current: Option<BundledFile> = None;
+    let mut current_dir = String::new();
+    let mut merged_dir: Option<String> = None;
+
+    for line in bundle.split_inclusive('\n') {
+        let marker = line.strip_suffix('\n').unwrap_or(line);
+        if marker == END_MARKER {
+            files.extend(current.take().map(finish));
+            return Ok(files);
+        }
+        if let Some(path) = header_path(marker) {
+            files.extend(current.take().map(finish));
+            merged_dir = None;
+            if let Some(dir) = path
+                .strip_suffix('*')
+                .filter(|dir| dir.is_empty() || dir.ends_with('/'))
+            {
+                merged_dir = Some(format!("{}{}", current_dir, dir));
+            } else if path.ends_with('/') {
+                current_dir = path.trim_start_matches("./").to_string();
+            } else {
+                current = Some(BundledFile {
+                    path: format!("{}{}", current_dir, path),
+                    contents: String::new(),
+                });
+            }
+        } else if let (Some(dir), Some(name)) = (&merged_dir, merged_file_name(marker)) {
+            files.extend(current.take().map(finish));
+            current = Some(BundledFile {
+                path: format!("{}{}", dir, name),
+                contents: String::new(),
+            });
+        } else if let Some(file) = &mut current {
+            file.contents.push_str(line);
+        }
+    }
+    anyhow::bail!("Bundle is missing the {} marker", END_MARKER)
+}
+
+fn finish(mut file: BundledFile) -> BundledFile {
+    if file.contents.ends_with('\n') {
+        file.contents.pop();
+    }
+    file
+}
+
+/// Writes each file of a bundle under `out_dir`, refusing paths that would
+/// escape it.
+pub fn unbundle(bundle_path: &Path, out_dir: &Path) -> Result<usize> {
+    let bundle = fs::read_to_string(bundle_path)
+        .with_context(|| format!("Could not read bundle {}", bundle_path.display()))?;
+    let files = parse(&bundle)?;
+    for file in &files {
+        let relative_path = Path::new(&file.path);
+        let escapes = relative_path
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            anyhow::bail!(
+                "Refusing to write {} outside the output directory",
+                file.path
+            );
+        }
+        let path = out_dir.join(relative_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &file.contents)
+            .with_context(|| format!("Could not write {}", path.display()))?;
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(bundle: &str) -> Vec<(String, String)> {
+        parse(bundle)
+            .unwrap()
+            .into_iter()
+            .map(|file| (file.path, file.contents))
+            .collect()
+    }
+
+    #[test]
+    fn parse_skips_the_preamble_notes_and_instructions() {
+        let bundle = "Preamble mentioning @@@@<file-path>@@@@.\n\
+            Note: 1 more .json file(s) were omitted by --sample-ext\n\
+            @@@@a.txt@@@@\nfirst\n\n\
+            @@@@dir/b.txt@@@@\nno newline\n\
+            @@@@END@@@@\nInstructions\n@@@@c.txt@@@@\n";
+        assert_eq!(
+            parsed(bundle),
+            [
+                ("a.txt".to_string(), "first\n".to_string()),
+                ("dir/b.txt".to_string(), "no newline".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_applies_compact_directory_headers() {
+        let bundle = "@@@@./@@@@\n@@@@a.txt@@@@\na\n\n\
+            @@@@src/@@@@\n@@@@b.rs@@@@\nb\n\n@@@@c.rs@@@@\nc\n\n@@@@END@@@@\n";
+        assert_eq!(
+            parsed(bundle),
+            [
+                ("a.txt".to_string(), "a\n".to_string()),
+                ("src/b.rs".to_string(), "b\n".to_string()),
+                ("src/c.rs".to_string(), "c\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_splits_merged_blocks() {
+        let bundle = "@@@@src/*@@@@\n--- a.rs ---\na\n\n--- b.rs ---\nb\n\n@@@@END@@@@\n";
+        assert_eq!(
+            parsed(bundle),
+            [
+                ("src/a.rs".to_string(), "a\n".to_string()),
+                ("src/b.rs".to_string(), "b\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_requires_the_end_marker() {
+        assert!(parse("@@@@a.txt@@@@\na\n").is_err());
+    }
+
+    #[test]
+    fn marker_lines_are_headers_and_the_end_marker() {
+        assert!(is_marker_line("@@@@END@@@@"));
+        assert!(is_marker_line("@@@@src/main.rs@@@@"));
+        assert!(!is_marker_line("@@@@@@@@"));
+        assert!(!is_marker_line(" @@@@a@@@@"));
+    }
+
+    #[test]
+    fn is_merged_file_line_matches_delimiters_only() {
+        assert!(is_merged_file_line("--- b.rs ---"));
+        assert!(!is_merged_file_line("--- ---"));
+        assert!(!is_merged_file_line("---"));
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Input prices in US dollars per million tokens, used when no `--pricing`
+/// file is given. They are list prices at the time of writing and may be out
+/// of date.
+const DEFAULT_PRICING: &[(&str, f64)] = &[
+    ("gpt-4o", 2.50),
+    ("gpt-4o-mini", 0.15),
+    ("claude-3-5-sonnet", 3.00),
+    ("claude-3-5-haiku", 0.80),
+    ("gemini-1.5-pro", 1.25),
+];
+
+/// Reads a pricing file of `<model> <dollars per million tokens>` lines.
+/// Blank lines and lines starting with `#` are skipped.
+fn read_pricing(pricing_path: &Path) -> Result<Vec<(String, f64)>> {
+    let contents = fs::read_to_string(pricing_path)
+        .with_context(|| format!("Could not read pricing file {}", pricing_path.display()))?;
+    let mut pricing = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (model, price) = line
+            .rsplit_once(char::is_whitespace)
+            .ok_or_else(|| anyhow::anyhow!("Malformed pricing line: {}", line))?;
+        let price: f64 = price
+            .trim_start_matches('$')
+            .parse()
+            .with_context(|| format!("Invalid price in pricing line: {}", line))?;
+        pricing.push((model.trim().to_string(), price));
+    }
+    Ok(pricing)
+}
+
+/// The cost in dollars of sending `tokens` at `price` dollars per million.
+fn cost(tokens: usize, price: f64) -> f64 {
+    tokens as f64 * price / 1_000_000.0
+}
+
+/// Estimates the tokens in a bundle, read from a file or from stdin when the
+/// path is `-`, and prints what sending it would cost with each model.
+pub fn run(bundle_path: &str, pricing_path: Option<&Path>) -> Result<()> {
+    let mut bundle = String::new();
+    if bundle_path == "-" {
+        io::stdin().read_to_string(&mut bundle)?;
+    } else {
+        bundle = fs::read_to_string(bundle_path)
+            .with_context(|| format!("Could not read bundle {}", bundle_path))?;
+    }
+
+    let pricing = match pricing_path {
+        Some(path) => read_pricing(path)?,
+        None => DEFAULT_PRICING
+            .iter()
+            .map(|&(model, price)| (model.to_string(), price))
+            .collect(),
+    };
+
+    let tokens = crate::estimate_tokens(&bundle);
+    println!("Estimated tokens: {}", tokens);
+    for (model, price) in pricing {
+        // Small bundles would otherwise all round to $0.00
+        let cost = cost(tokens, price);
+        let precision = if cost < 0.01 { 4 } else { 2 };
+        println!("{}: ~${:.*}", model, precision, cost);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_is_linear_in_tokens() {
+        assert_eq!(cost(0, 3.0), 0.0);
+        assert_eq!(cost(1_000_000, 3.0), 3.0);
+        assert_eq!(cost(2_000_000, 3.0), 2.0 * cost(1_000_000, 3.0));
+        assert_eq!(cost(500, 2.0), 0.001);
+    }
+}
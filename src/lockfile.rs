@@ -0,0 +1,102 @@
+use serde_json::Value;
+
+/// Whether a file name is that of a lockfile whose dependencies
+/// `dependency_count` can count.
+pub fn is_lockfile(file_name: &str) -> bool {
+    matches!(
+        file_name,
+        "Cargo.lock"
+            | "poetry.lock"
+            | "uv.lock"
+            | "yarn.lock"
+            | "Gemfile.lock"
+            | "package-lock.json"
+            | "composer.lock"
+    )
+}
+
+/// Counts the packages pinned by a lockfile, or returns `None` if its format
+/// is not recognized or it cannot be parsed.
+pub fn dependency_count(file_name: &str, contents: &str) -> Option<usize> {
+    match file_name {
+        "Cargo.lock" | "poetry.lock" | "uv.lock" => Some(
+            contents
+                .lines()
+                .filter(|line| line.trim_end() == "[[package]]")
+                .count(),
+        ),
+        // Each entry starts with an unindented `"name@range", ...:` line
+        "yarn.lock" => Some(
+            contents
+                .lines()
+                .filter(|line| {
+                    !line.starts_with(char::is_whitespace)
+                        && !line.starts_with('#')
+                        && !line.starts_with("__metadata")
+                        && line.trim_end().ends_with(':')
+                })
+                .count(),
+        ),
+        // Gems are listed four spaces deep under `specs:`, their own
+        // dependencies six deep
+        "Gemfile.lock" => Some(
+            contents
+                .lines()
+                .filter(|line| line.starts_with("    ") && !line.starts_with("     "))
+                .count(),
+        ),
+        "package-lock.json" => {
+            let lock: Value = serde_json::from_str(contents).ok()?;
+            match lock.get("packages").and_then(Value::as_object) {
+                // The root project is listed under the empty key
+                Some(packages) => Some(packages.keys().filter(|key| !key.is_empty()).count()),
+                None => Some(lock.get("dependencies")?.as_object()?.len()),
+            }
+        }
+        "composer.lock" => {
+            let lock: Value = serde_json::from_str(contents).ok()?;
+            let count = |key: &str| lock.get(key).and_then(Value::as_array).map_or(0, Vec::len);
+            Some(count("packages") + count("packages-dev"))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_cargo_lock_packages() {
+        let lock = "version = 3\n\n[[package]]\nname = \"a\"\n\n[[package]]\nname = \"b\"\n";
+        assert_eq!(dependency_count("Cargo.lock", lock), Some(2));
+    }
+
+    #[test]
+    fn counts_yarn_and_gemfile_entries() {
+        let yarn = "# yarn lockfile v1\n\n\"left-pad@^1.0.0\", left-pad@^1.1.0:\n  version \"1.3.0\"\n\nlodash@^4:\n  version \"4.17.21\"\n";
+        assert_eq!(dependency_count("yarn.lock", yarn), Some(2));
+
+        let gemfile =
+            "GEM\n  specs:\n    rack (2.2.8)\n    rails (7.1.0)\n      rack (>= 2)\n\nPLATFORMS\n";
+        assert_eq!(dependency_count("Gemfile.lock", gemfile), Some(2));
+    }
+
+    #[test]
+    fn counts_json_lockfiles() {
+        let npm = r#"{"packages": {"": {}, "node_modules/a": {}, "node_modules/b": {}}}"#;
+        assert_eq!(dependency_count("package-lock.json", npm), Some(2));
+        let npm_v1 = r#"{"dependencies": {"a": {}}}"#;
+        assert_eq!(dependency_count("package-lock.json", npm_v1), Some(1));
+        let composer = r#"{"packages": [{}, {}], "packages-dev": [{}]}"#;
+        assert_eq!(dependency_count("composer.lock", composer), Some(3));
+        assert_eq!(dependency_count("package-lock.json", "not json"), None);
+    }
+
+    #[test]
+    fn only_known_lockfiles_are_recognized() {
+        assert!(is_lockfile("Cargo.lock"));
+        assert!(!is_lockfile("flake.lock"));
+        assert_eq!(dependency_count("flake.lock", "{}"), None);
+    }
+}
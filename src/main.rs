@@ -1,41 +1,195 @@
 use anyhow::{Context, Result};
-use clap::{Arg, Command};
-use git2::{Repository, StatusOptions, StatusShow};
-use globset::GlobSetBuilder;
+use base64::prelude::{BASE64_STANDARD, Engine};
+use clap::{Arg, ArgMatches, Command};
+use git2::{ObjectType, Oid, Repository, StatusOptions, StatusShow};
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::WalkState;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs::File;
+use std::hash::Hash;
 use std::io::{self, BufReader, BufWriter, Read, Write, stdout};
 use std::path::Path;
 use std::path::PathBuf;
-use std::time::SystemTime;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use style::Style;
+use unicode_normalization::UnicodeNormalization;
 use walkdir::WalkDir;
 
-fn is_binary(file_path: &Path) -> Result<bool> {
+mod archive;
+mod bundle;
+mod cost;
+mod lockfile;
+mod module_order;
+mod notebook;
+mod repomap;
+mod signatures;
+mod style;
+
+/// A file is binary if its first KiB contains a NUL byte, or if more than
+/// `threshold` of those bytes are control characters other than whitespace.
+/// Bytes above ASCII count as printable so that other encodings pass.
+fn is_binary(file_path: &Path, threshold: f64) -> Result<bool> {
     let mut buffer = [0; 1024];
     let mut reader = BufReader::new(File::open(file_path)?);
     let mut total_read = 0;
 
-    loop {
-        let read = reader.read(&mut buffer)?;
+    while total_read < buffer.len() {
+        let read = reader.read(&mut buffer[total_read..])?;
         if read == 0 {
             break;
         }
-        if buffer.iter().take(read).any(|&byte| byte == 0) {
+        if buffer[total_read..total_read + read].contains(&0) {
             return Ok(true);
         }
         total_read += read;
-        if total_read >= 1024 {
-            break;
+    }
+
+    let non_printable = buffer[..total_read]
+        .iter()
+        .filter(|&&byte| {
+            (byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || byte == 0x7f
+        })
+        .count();
+    Ok(total_read > 0 && non_printable as f64 / total_read as f64 > threshold)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum UnicodeForm {
+    Nfc,
+    Nfkc,
+}
+
+#[derive(Clone, Debug, Default)]
+struct ProcessOptions {
+    raw: bool,
+    normalize_unicode: Option<UnicodeForm>,
+    strip_docstrings: bool,
+    signatures_only: bool,
+    notebook_outputs: bool,
+    head_tail: Option<usize>,
+}
+
+/// Zero-width characters and the byte order mark, which are invisible but
+/// still cost tokens.
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}'
+    )
+}
+
+fn normalize_unicode(content: &str, form: UnicodeForm) -> String {
+    let visible = content.chars().filter(|&c| !is_invisible(c));
+    match form {
+        UnicodeForm::Nfc => visible.nfc().collect(),
+        UnicodeForm::Nfkc => visible.nfkc().collect(),
+    }
+}
+
+/// Returns the quote that opens a string literal statement, if the line is one.
+fn string_statement_quote(trimmed: &str) -> Option<&'static str> {
+    let unprefixed = trimmed.trim_start_matches(['r', 'R', 'u', 'U']);
+    ["\"\"\"", "'''", "\"", "'"]
+        .into_iter()
+        .find(|quote| unprefixed.starts_with(quote))
+}
+
+/// Removes module, class and function docstrings from Python source: the
+/// string literal statement that opens each of those blocks. Strings used as
+/// values, or appearing later in a block, are kept.
+fn strip_python_docstrings(content: &str) -> String {
+    let mut stripped = String::new();
+    let mut expect_docstring = true;
+    let mut in_block_header = false;
+    let mut header_depth = 0i32;
+    let mut closing_quote: Option<&str> = None;
+
+    for line in content.lines() {
+        if let Some(quote) = closing_quote {
+            if line.contains(quote) {
+                closing_quote = None;
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if expect_docstring {
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                stripped.push_str(line);
+                stripped.push('\n');
+                continue;
+            }
+            expect_docstring = false;
+            if let Some(quote) = string_statement_quote(trimmed) {
+                let after_open = &trimmed[trimmed.find(quote).unwrap_or(0) + quote.len()..];
+                if quote.len() == 3 && !after_open.contains(quote) {
+                    closing_quote = Some(quote);
+                }
+                continue;
+            }
+        }
+
+        if trimmed.starts_with("def ")
+            || trimmed.starts_with("async def ")
+            || trimmed.starts_with("class ")
+        {
+            in_block_header = true;
+            header_depth = 0;
+        }
+        if in_block_header {
+            let code = trimmed.split('#').next().unwrap_or("").trim_end();
+            for c in code.chars() {
+                match c {
+                    '(' | '[' | '{' => header_depth += 1,
+                    ')' | ']' | '}' => header_depth -= 1,
+                    _ => {}
+                }
+            }
+            // A header spans lines until its brackets balance
+            if header_depth <= 0 {
+                in_block_header = false;
+                expect_docstring = code.ends_with(':');
+            }
         }
+
+        stripped.push_str(line);
+        stripped.push('\n');
     }
-    Ok(false)
+    stripped
 }
 
-fn process_file_contents(file_path: &Path, content: &str) -> String {
+fn process_file_contents(file_path: &Path, content: &str, options: &ProcessOptions) -> String {
+    if options.raw {
+        return content.to_string();
+    }
+
     let extension = file_path
         .extension()
         .and_then(|os_str| os_str.to_str())
         .unwrap_or("");
 
+    let mut content = Cow::Borrowed(content);
+    if extension == "ipynb" {
+        if let Some(rendered) = notebook::render(&content, options.notebook_outputs) {
+            content = Cow::Owned(rendered);
+        }
+    }
+    if let Some(form) = options.normalize_unicode {
+        content = Cow::Owned(normalize_unicode(&content, form));
+    }
+    if options.strip_docstrings && extension == "py" {
+        content = Cow::Owned(strip_python_docstrings(&content));
+    }
+    if options.signatures_only {
+        if let Some(elided) = signatures::elide_bodies(extension, &content) {
+            content = Cow::Owned(elided);
+        }
+    }
+
     let significant_whitespace_extensions = [
         "py", "nim", "hs", "yml", "yaml", "coffee", "jade", "pug", "slim", "sass", "haml",
     ];
@@ -62,192 +216,3421 @@ fn process_file_contents(file_path: &Path, content: &str) -> String {
             processed.push('\n');
         }
     }
-    processed
+    match options.head_tail {
+        Some(lines) => keep_head_and_tail(&processed, lines).unwrap_or(processed),
+        None => processed,
+    }
 }
 
-fn is_child_of(child: &str, parent: &str) -> bool {
-    let parent = parent.trim_end_matches('/');
-    child.starts_with(parent)
-        && (child.len() == parent.len() || child[parent.len()..].starts_with('/'))
+/// Keeps the first and last `lines` lines of content that is longer than
+/// both together, marking how many lines were left out in between.
+fn keep_head_and_tail(content: &str, lines: usize) -> Option<String> {
+    let all: Vec<&str> = content.lines().collect();
+    let omitted = all
+        .len()
+        .checked_sub(lines.checked_mul(2)?)
+        .filter(|&omitted| omitted > 0)?;
+    let mut kept = String::new();
+    for line in &all[..lines] {
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    kept.push_str(&format!("[... {} lines omitted ...]\n", omitted));
+    for line in &all[all.len() - lines..] {
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    Some(kept)
 }
 
-fn main() -> Result<()> {
-    let matches = Command::new("gprepo")
-        .version("0.1.0")
-        .arg(
-            Arg::new("output")
-                .short('o')
-                .long("output")
-                .value_name("OUTPUT_PATH")
-                .help("Output to path (default: stdout)")
-                .required(false),
-        )
-        .arg(
-            Arg::new("repo_path")
-                .short('r')
-                .long("repo-path")
-                .value_name("REPO_PATH")
-                .help("Path to the repository")
-                .required(false),
-        )
-        .arg(
-            Arg::new("preamble")
-                .short('p')
-                .long("preamble")
-                .value_name("PREAMBLE_PATH")
-                .help("Optional path to the preamble file")
-                .required(false),
-        )
-        .arg(
-            Arg::new("exclude")
-                .short('e')
-                .long("exclude")
-                .value_name("EXCLUDE_PATH")
-                .help("File paths to exclude (supports glob patterns)")
-                .required(false)
-                .num_args(1..)
-                .action(clap::ArgAction::Append),
-        )
-        .arg(
-            Arg::new("include")
-                .short('i')
-                .long("include")
-                .value_name("INCLUDE_PATH")
-                .help("Only process these specific paths (supports glob patterns)")
-                .required(false)
-                .num_args(1..)
-                .action(clap::ArgAction::Append),
-        )
-        .get_matches();
+/// Location of the cached processed contents of a file. The key covers the
+/// file's absolute path, modification time and size, the processing options
+/// and the gprepo version, so a hit needs only the file's metadata and any
+/// change to them misses the cache.
+fn cache_entry_path(
+    cache_dir: &Path,
+    file_path: &Path,
+    options: &ProcessOptions,
+) -> Result<PathBuf> {
+    let metadata = std::fs::metadata(file_path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let key = format!(
+        "{}\0{}\0{}.{:09}\0{}\0{:?}",
+        env!("CARGO_PKG_VERSION"),
+        file_path.display(),
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len(),
+        options
+    );
+    let hash = Oid::hash_object(ObjectType::Blob, key.as_bytes())?;
+    Ok(cache_dir.join(hash.to_string()))
+}
 
-    let output_path: Option<PathBuf> = matches.get_one::<String>("output").map(PathBuf::from);
-    let process_start_time = SystemTime::now();
+/// Time spent in each phase of a run, how many files were opened to detect
+/// binary ones and how often `--cache-dir` was hit, reported by `--timings`.
+#[derive(Default)]
+struct Timings {
+    walking: Duration,
+    filtering: Duration,
+    binary_detection: Duration,
+    reading: Duration,
+    processing: Duration,
+    writing: Duration,
+    binary_scans: usize,
+    cache_hits: usize,
+    cache_misses: usize,
+}
 
-    let repo = match matches.get_one::<String>("repo_path") {
-        Some(path) => Repository::discover(path).context("Could not find repository")?,
-        None => {
-            let current_dir = std::env::current_dir()?;
-            Repository::discover(current_dir).context("Could not find repository")?
+impl Timings {
+    fn report(&self, style: &Style) {
+        eprintln!("{}", style.heading("Timings:"));
+        for (phase, duration) in [
+            ("walking", self.walking),
+            ("filtering", self.filtering),
+            ("binary detection", self.binary_detection),
+            ("reading", self.reading),
+            ("processing", self.processing),
+            ("writing", self.writing),
+        ] {
+            eprintln!("  {:<18}{:.3?}", phase, duration);
         }
+        eprintln!("  {:<18}{} file(s)", "binary scans", self.binary_scans);
+        if self.cache_hits + self.cache_misses > 0 {
+            eprintln!(
+                "  {:<18}{} hit(s), {} miss(es)",
+                "cache", self.cache_hits, self.cache_misses
+            );
+        }
+    }
+}
+
+/// Everything emitted for one file. It is read in full before anything is
+/// written, so a file that fails leaves no partial block behind.
+struct FileBlock {
+    /// Each written as a `Note:` line after the header: the sidecar note, if
+    /// any, then any note on how the file was included.
+    notes: Vec<String>,
+    symbols: Vec<String>,
+    line_endings: Option<LineEndings>,
+    non_ascii: usize,
+    contents: String,
+    /// Files combined into this block by `--merge-under`, each written after
+    /// a `--- <name> ---` line. Empty for a block holding a single file.
+    merged: Vec<(PathBuf, FileBlock)>,
+}
+
+impl FileBlock {
+    /// Bytes of contents, including those of any merged files.
+    fn size(&self) -> usize {
+        self.contents.len()
+            + self
+                .merged
+                .iter()
+                .map(|(_, block)| block.size())
+                .sum::<usize>()
+    }
+}
+
+/// The paths of the files a block holds: its merged files, or just itself.
+fn block_paths<'a>(path: &'a Path, block: &'a FileBlock) -> Vec<&'a Path> {
+    if block.merged.is_empty() {
+        vec![path]
+    } else {
+        block
+            .merged
+            .iter()
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+}
+
+/// Settings for reading file blocks, shared by every file in a run.
+struct BlockOptions<'a> {
+    cache_dir: Option<&'a Path>,
+    repomap: bool,
+    line_endings: bool,
+    ascii_only: Option<AsciiMode>,
+    process: &'a ProcessOptions,
+}
+
+/// What `--ascii-only` does with each non-ASCII character.
+#[derive(Clone, Copy)]
+enum AsciiMode {
+    Strip,
+    Replace,
+}
+
+/// Strips non-ASCII characters or replaces each with `?`, returning the
+/// result and how many characters were affected.
+fn to_ascii(content: String, mode: AsciiMode) -> (String, usize) {
+    let non_ascii = content.chars().filter(|c| !c.is_ascii()).count();
+    if non_ascii == 0 {
+        return (content, 0);
+    }
+    let ascii = match mode {
+        AsciiMode::Strip => content.chars().filter(char::is_ascii).collect(),
+        AsciiMode::Replace => content
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '?' })
+            .collect(),
     };
+    (ascii, non_ascii)
+}
 
-    let repo_path = repo
-        .workdir()
-        .ok_or_else(|| anyhow::anyhow!("Could not find repository working directory"))?;
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LineEndings {
+    Lf,
+    Crlf,
+    Mixed,
+}
 
-    let mut _gitignore = repo
-        .statuses(Some(
-            StatusOptions::new()
-                .include_ignored(false)
-                .show(StatusShow::IndexAndWorkdir),
-        ))
-        .context("Failed to read gitignore")?;
+fn line_endings(content: &str) -> LineEndings {
+    let crlf = content.matches("\r\n").count();
+    let lf = content.matches('\n').count() - crlf;
+    match (crlf, lf) {
+        (0, _) => LineEndings::Lf,
+        (_, 0) => LineEndings::Crlf,
+        _ => LineEndings::Mixed,
+    }
+}
 
-    let exclude_set = {
-        let mut builder = GlobSetBuilder::new();
-        if let Some(exclude_paths) = matches.get_many::<String>("exclude") {
-            for path in exclude_paths {
-                builder.add(path.parse().unwrap());
+fn read_file_block(
+    file_path: &Path,
+    block_options: &BlockOptions,
+    timings: &mut Timings,
+) -> Result<FileBlock> {
+    let options = block_options.process;
+    let started = Instant::now();
+    let mut processing = Duration::ZERO;
+    let cache_entry = match block_options.cache_dir {
+        Some(dir) => Some(cache_entry_path(dir, file_path, options)?),
+        None => None,
+    };
+    let mut source = None;
+    let contents = match cache_entry.as_deref().filter(|entry| entry.is_file()) {
+        Some(entry) => {
+            timings.cache_hits += 1;
+            std::fs::read_to_string(entry)?
+        }
+        None => {
+            if cache_entry.is_some() {
+                timings.cache_misses += 1;
             }
+            let file_contents = std::fs::read_to_string(file_path)?;
+            let processing_started = Instant::now();
+            let processed = process_file_contents(file_path, &file_contents, options);
+            processing += processing_started.elapsed();
+            if let Some(entry) = &cache_entry {
+                std::fs::write(entry, &processed)?;
+            }
+            source = Some(file_contents);
+            processed
         }
-        // Add default patterns
-        builder.add("*changelog*".parse().unwrap());
-        builder.add("*CHANGELOG*".parse().unwrap());
-        builder.add(".github*".parse().unwrap());
-        builder.add(".gitignore".parse().unwrap());
-        builder.add("gprepo".parse().unwrap());
-        builder.add("*LICENSE*".parse().unwrap());
-        builder.add("*.lock".parse().unwrap());
-        builder.add("*README*".parse().unwrap());
-        builder.build().unwrap()
     };
 
-    let mut writer: Box<dyn Write> = match matches.get_one::<String>("output") {
-        Some(output_path) => Box::new(BufWriter::new(File::create(output_path)?)),
-        None => Box::new(BufWriter::new(stdout())),
+    // Applied after the cache, which holds contents before this step, so
+    // that the characters affected can be counted
+    let (contents, non_ascii) = match block_options.ascii_only {
+        Some(mode) => to_ascii(contents, mode),
+        None => (contents, 0),
     };
 
-    if let Some(preamble_path) = matches.get_one::<String>("preamble") {
-        let mut preamble = String::new();
-        File::open(preamble_path)?.read_to_string(&mut preamble)?;
-        writeln!(writer, "{}", preamble)?;
+    // Cached blocks still need the source for features that inspect it
+    if source.is_none() && (block_options.repomap || block_options.line_endings) {
+        source = Some(std::fs::read_to_string(file_path)?);
+    }
+    let line_endings = match &source {
+        Some(source) if block_options.line_endings => Some(line_endings(source)),
+        _ => None,
+    };
+
+    let symbols = match &source {
+        Some(source) if block_options.repomap => {
+            let extension = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            let processing_started = Instant::now();
+            let symbols = repomap::outline(extension, source);
+            processing += processing_started.elapsed();
+            symbols
+        }
+        _ => Vec::new(),
+    };
+
+    let notes = read_sidecar(file_path)?.into_iter().collect();
+
+    timings.processing += processing;
+    timings.reading += started.elapsed().saturating_sub(processing);
+    Ok(FileBlock {
+        notes,
+        symbols,
+        line_endings,
+        non_ascii,
+        contents,
+        merged: Vec::new(),
+    })
+}
+
+/// Reads the sidecar note of a file, if it has one.
+fn read_sidecar(file_path: &Path) -> Result<Option<String>> {
+    let sidecar = sidecar_path(file_path);
+    if sidecar.is_file() {
+        Ok(Some(std::fs::read_to_string(&sidecar)?))
     } else {
-        writeln!(
-            writer,
-            "Below is a repository containing files. Each file begins with @@@@<file-path>@@@@ followed by its content. The repository ends with @@@@END@@@@. After this marker, instructions related to the repository are provided."
-        )?;
+        Ok(None)
     }
+}
 
-    for entry in WalkDir::new(repo_path) {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            let file_path = entry.path();
-            let relative_file_path = file_path.strip_prefix(repo_path).unwrap();
-            let path_str = relative_file_path.to_str().unwrap_or("");
+/// Reads a binary file forced in with `--force-include` as base64, wrapped
+/// at 76 columns.
+fn read_binary_block(file_path: &Path) -> Result<FileBlock> {
+    let encoded = BASE64_STANDARD.encode(std::fs::read(file_path)?);
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect();
+    let mut notes: Vec<String> = read_sidecar(file_path)?.into_iter().collect();
+    notes.push("binary file; contents are base64-encoded".to_string());
+    Ok(FileBlock {
+        notes,
+        symbols: Vec::new(),
+        line_endings: None,
+        non_ascii: 0,
+        contents: lines.join("\n"),
+        merged: Vec::new(),
+    })
+}
 
-            let mut should_exclude = false;
-            if let Some(exclude_paths) = matches.get_many::<String>("exclude") {
-                for exclude_path in exclude_paths {
-                    if is_child_of(path_str, exclude_path) {
-                        should_exclude = true;
-                        break;
-                    }
-                }
-            }
+/// Stands in for a file too far from the `--progressive` focus to include.
+fn path_only_block(file_path: &Path) -> Result<FileBlock> {
+    let mut notes: Vec<String> = read_sidecar(file_path)?.into_iter().collect();
+    notes.push("far from the focus path; contents omitted".to_string());
+    Ok(FileBlock {
+        notes,
+        symbols: Vec::new(),
+        line_endings: None,
+        non_ascii: 0,
+        contents: String::new(),
+        merged: Vec::new(),
+    })
+}
 
-            let mut should_include = matches.get_many::<String>("include").is_none();
-            if let Some(include_path) = matches.get_many::<String>("include") {
-                for include_path in include_path {
-                    if is_child_of(path_str, include_path) {
-                        should_include = true;
-                        break;
-                    }
-                }
-            }
+/// Counts the steps from `dir` to the directory holding `file`: up to their
+/// deepest common ancestor, then down.
+fn tree_distance(dir: &Path, file: &Path) -> usize {
+    let from: Vec<_> = dir.components().collect();
+    let to: Vec<_> = file
+        .parent()
+        .unwrap_or(Path::new(""))
+        .components()
+        .collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    from.len() - common + to.len() - common
+}
 
-            if should_exclude || !should_include {
-                continue;
-            }
+fn is_child_of(child: &str, parent: &str) -> bool {
+    let parent = parent.trim_end_matches('/');
+    child.starts_with(parent)
+        && (child.len() == parent.len() || child[parent.len()..].starts_with('/'))
+}
 
-            if exclude_set.is_match(path_str) {
-                continue;
-            }
+const SIDECAR_SUFFIX: &str = ".gprepo.md";
 
-            let should_ignore = repo.status_should_ignore(relative_file_path).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed to check if path should be ignored: {:?}", e),
-                )
-            })?;
-            if should_ignore {
-                continue;
-            }
+fn sidecar_path(file_path: &Path) -> PathBuf {
+    let mut sidecar = file_path.as_os_str().to_owned();
+    sidecar.push(SIDECAR_SUFFIX);
+    PathBuf::from(sidecar)
+}
 
-            if output_path
-                .as_ref()
-                .is_some_and(|op| op.as_path() == file_path)
-            {
-                continue;
+struct Author {
+    name: String,
+    email: String,
+}
+
+impl Author {
+    fn matches(&self, name_or_email: &str) -> bool {
+        self.name == name_or_email || self.email.eq_ignore_ascii_case(name_or_email)
+    }
+}
+
+/// Maps each path to the author of the most recent commit that touched it,
+/// computed in a single walk of the history reachable from HEAD. Merge
+/// commits are skipped, since diffing them against their first parent would
+/// credit the merger with every change brought in from the other side.
+fn last_authors(repo: &Repository) -> Result<HashMap<PathBuf, Author>> {
+    let mut authors = HashMap::new();
+    if repo.head().is_err() {
+        return Ok(authors);
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let tree = commit.tree()?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+        let signature = commit.author();
+        for delta in diff.deltas() {
+            if let Some(path) = delta.new_file().path() {
+                authors.entry(path.to_path_buf()).or_insert_with(|| Author {
+                    name: signature.name().unwrap_or("").to_string(),
+                    email: signature.email().unwrap_or("").to_string(),
+                });
             }
+        }
+    }
+    Ok(authors)
+}
 
-            let metadata = file_path.metadata()?;
-            if let Ok(modified_time) = metadata.modified() {
-                if modified_time >= process_start_time {
-                    continue;
-                }
+/// Lists files with uncommitted working-tree changes, relative to the
+/// repository. Changes that are only staged are left out.
+fn dirty_paths(repo: &Repository) -> Result<HashSet<PathBuf>> {
+    let statuses = repo.statuses(Some(
+        StatusOptions::new()
+            .show(StatusShow::Workdir)
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_ignored(false),
+    ))?;
+    let changed = git2::Status::WT_NEW
+        | git2::Status::WT_MODIFIED
+        | git2::Status::WT_TYPECHANGE
+        | git2::Status::WT_RENAMED;
+    Ok(statuses
+        .iter()
+        .filter(|entry| entry.status().intersects(changed))
+        .filter_map(|entry| entry.path().map(PathBuf::from))
+        .collect())
+}
+
+/// Names the default branch: the one `origin/HEAD` points at, else the
+/// configured `init.defaultBranch` if that branch exists, else the checked
+/// out branch.
+fn default_branch(repo: &Repository, head: &git2::Reference) -> String {
+    let remote_head = repo
+        .find_reference("refs/remotes/origin/HEAD")
+        .ok()
+        .and_then(|reference| reference.symbolic_target().map(String::from));
+    if let Some(name) = remote_head
+        .as_deref()
+        .and_then(|target| target.strip_prefix("refs/remotes/origin/"))
+    {
+        return name.to_string();
+    }
+    let configured = repo
+        .config()
+        .ok()
+        .and_then(|config| config.get_string("init.defaultBranch").ok());
+    if let Some(name) =
+        configured.filter(|name| repo.find_branch(name, git2::BranchType::Local).is_ok())
+    {
+        return name;
+    }
+    if head.is_branch() {
+        head.shorthand().unwrap_or("unknown").to_string()
+    } else {
+        "detached HEAD".to_string()
+    }
+}
+
+/// Describes the default branch, the size of the checked out history and its
+/// latest commit, one line each.
+fn repo_summary(repo: &Repository) -> Result<String> {
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => {
+            return Ok("Repository summary: no commits yet.\n".to_string());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let branch = default_branch(repo, &head);
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    let mut commits = 0;
+    let mut contributors = HashSet::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        commits += 1;
+        contributors.insert(commit.author().email().unwrap_or("").to_string());
+    }
+    let latest = head.peel_to_commit()?;
+
+    Ok(format!(
+        "Repository summary:\n- Default branch: {}\n- Commits: {}\n- Contributors: {}\n- Latest commit: {}\n",
+        branch,
+        commits,
+        contributors.len(),
+        latest.summary().unwrap_or("")
+    ))
+}
+
+/// Formats the git note attached to the checked out commit for the preamble,
+/// or returns `None` if there are no commits or the commit has no note.
+fn head_notes(repo: &Repository) -> Result<Option<String>> {
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let commit = head.peel_to_commit()?;
+    let note = match repo.find_note(None, commit.id()) {
+        Ok(note) => note,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let message = note.message().unwrap_or("").trim_end();
+    if message.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!("Notes on the latest commit:\n{}\n", message)))
+}
+
+/// Reads a manifest written by `--manifest`: one `<hash> <path>` line per file.
+fn read_manifest(manifest_path: &Path) -> Result<BTreeMap<String, String>> {
+    let mut contents = String::new();
+    File::open(manifest_path)
+        .with_context(|| format!("Could not open manifest {}", manifest_path.display()))?
+        .read_to_string(&mut contents)?;
+
+    let mut manifest = BTreeMap::new();
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let (hash, path) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow::anyhow!("Malformed manifest line: {}", line))?;
+        manifest.insert(path.to_string(), hash.to_string());
+    }
+    Ok(manifest)
+}
+
+fn write_manifest(writer: &mut dyn Write, manifest: &BTreeMap<String, String>) -> Result<()> {
+    for (path, hash) in manifest {
+        writeln!(writer, "{} {}", hash, path)?;
+    }
+    Ok(())
+}
+
+/// Writes `A`, `M` and `D` lines for paths added, modified and removed
+/// relative to a previous manifest.
+fn write_manifest_changes(
+    writer: &mut dyn Write,
+    previous: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+) -> Result<()> {
+    let mut changes: BTreeMap<&str, char> = BTreeMap::new();
+    for (path, hash) in current {
+        match previous.get(path) {
+            None => {
+                changes.insert(path, 'A');
             }
+            Some(previous_hash) if previous_hash != hash => {
+                changes.insert(path, 'M');
+            }
+            Some(_) => {}
+        }
+    }
+    for path in previous.keys() {
+        if !current.contains_key(path) {
+            changes.insert(path, 'D');
+        }
+    }
+    for (path, change) in changes {
+        writeln!(writer, "{} {}", change, path)?;
+    }
+    Ok(())
+}
 
-            if is_binary(file_path)? {
-                continue;
+/// Returns the stem of the source file a test file covers, recognising
+/// `test_x`, `x_test`, `x.test`, `x_spec` and `x.spec` naming conventions.
+fn tested_stem(path: &Path) -> Option<&str> {
+    let stem = path.file_stem()?.to_str()?;
+    stem.strip_prefix("test_")
+        .or_else(|| stem.strip_suffix("_test"))
+        .or_else(|| stem.strip_suffix(".test"))
+        .or_else(|| stem.strip_suffix("_spec"))
+        .or_else(|| stem.strip_suffix(".spec"))
+        .filter(|stem| !stem.is_empty())
+}
+
+/// File names moved to the front by `--entrypoints-first` unless overridden
+/// with `--entrypoint`.
+const DEFAULT_ENTRYPOINTS: &[&str] = &[
+    "main.rs",
+    "lib.rs",
+    "main.go",
+    "__main__.py",
+    "main.py",
+    "index.ts",
+    "index.js",
+];
+
+/// Directories of test fixtures skipped by `--exclude-test-data` unless
+/// overridden with `--test-data-dir`.
+const DEFAULT_TEST_DATA_DIRS: &[&str] = &["testdata", "fixtures", "__snapshots__", "golden"];
+
+/// Package manifests moved to the front, and emitted unprocessed, by
+/// `--manifests-first`.
+const PACKAGE_MANIFESTS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+    "requirements.txt",
+    "Gemfile",
+    "pom.xml",
+    "build.gradle",
+    "composer.json",
+];
+
+fn is_package_manifest(file: &Path) -> bool {
+    file.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| PACKAGE_MANIFESTS.contains(&name))
+}
+
+/// Moves files with one of the given file names to the front, ordered by the
+/// position of their name in `names`. Files keep their order otherwise.
+fn order_names_first(files: &mut [PathBuf], names: &[&str]) {
+    files.sort_by_key(|file| {
+        let name = file.file_name().and_then(|name| name.to_str());
+        names
+            .iter()
+            .position(|candidate| Some(*candidate) == name)
+            .unwrap_or(names.len())
+    });
+}
+
+/// Moves each test file to immediately after the source file it tests.
+///
+/// A test pairs with a source file of the same extension and stem, preferring
+/// one in the same directory. Tests without a matching source keep their place,
+/// as do tests whose match is itself a test being moved, such as
+/// `foo_test_test.rs` for `foo_test.rs`.
+fn order_tests_after_source(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut paired_source: Vec<Option<usize>> = vec![None; files.len()];
+    for (test_index, test) in files.iter().enumerate() {
+        let Some(stem) = tested_stem(test) else {
+            continue;
+        };
+        let candidates: Vec<usize> = files
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| {
+                source.file_stem().and_then(|s| s.to_str()) == Some(stem)
+                    && source.extension() == test.extension()
+            })
+            .map(|(index, _)| index)
+            .collect();
+        paired_source[test_index] = candidates
+            .iter()
+            .find(|&&index| files[index].parent() == test.parent())
+            .or_else(|| candidates.first())
+            .copied();
+    }
+    let moved: Vec<bool> = paired_source.iter().map(Option::is_some).collect();
+    for source in &mut paired_source {
+        if source.is_some_and(|index| moved[index]) {
+            *source = None;
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(files.len());
+    for (index, file) in files.iter().enumerate() {
+        if paired_source[index].is_some() {
+            continue;
+        }
+        ordered.push(file.clone());
+        for (test_index, source) in paired_source.iter().enumerate() {
+            if *source == Some(index) {
+                ordered.push(files[test_index].clone());
             }
+        }
+    }
+    ordered
+}
+
+/// Regroups files so that each directory's files are contiguous, as compact
+/// headers need. Directories are placed in the order of their first file, and
+/// files keep their order within a directory.
+fn group_by_dir(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut first_seen: HashMap<PathBuf, usize> = HashMap::new();
+    for file in &files {
+        let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let next = first_seen.len();
+        first_seen.entry(dir).or_insert(next);
+    }
+    let mut files = files;
+    files.sort_by_key(|file| first_seen[file.parent().unwrap_or(Path::new(""))]);
+    files
+}
+
+fn parse_sample_ext(value: &str) -> Result<(String, usize), String> {
+    let (extension, count) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <EXT>=<N>, got '{}'", value))?;
+    let count = count
+        .parse()
+        .map_err(|_| format!("invalid file count '{}'", count))?;
+    Ok((extension.trim_start_matches('.').to_string(), count))
+}
 
-            writeln!(writer, "@@@@{}@@@@", relative_file_path.display())?;
-            let mut file_contents = String::new();
-            File::open(file_path)?.read_to_string(&mut file_contents)?;
-            let processed_contents = process_file_contents(file_path, &file_contents);
-            writeln!(writer, "{}", processed_contents)?;
+/// Keeps at most a limit of files per group, taking the first in path order,
+/// and returns how many files of each group were dropped. `group` gives a
+/// file's group and that group's limit, or `None` for files without a cap.
+fn cap_per_group<K: Clone + Eq + Hash + Ord>(
+    files: &mut Vec<PathBuf>,
+    group: impl Fn(&Path) -> Option<(K, usize)>,
+) -> BTreeMap<K, usize> {
+    let mut sorted: Vec<&PathBuf> = files.iter().collect();
+    sorted.sort();
+
+    let mut kept: HashMap<K, usize> = HashMap::new();
+    let mut dropped_paths = HashSet::new();
+    let mut dropped = BTreeMap::new();
+    for file in sorted {
+        let Some((key, limit)) = group(file) else {
+            continue;
+        };
+        let count = kept.entry(key.clone()).or_insert(0);
+        if *count < limit {
+            *count += 1;
+        } else {
+            *dropped.entry(key).or_insert(0) += 1;
+            dropped_paths.insert(file.clone());
         }
     }
-    writeln!(writer, "@@@@END@@@@")?;
-    Ok(())
+    files.retain(|file| !dropped_paths.contains(file));
+    dropped
+}
+
+/// Parses a bundle rendered in memory back and checks that every file in
+/// `sources`, which maps emitted paths to the files they were read from,
+/// comes out byte for byte as it is on disk. Contents that contain a header
+/// or END marker line, notes and other lines written into a block, and files
+/// that could not be read at all are reported as mismatches.
+fn validate_round_trip(
+    bundle: Vec<u8>,
+    sources: &BTreeMap<String, PathBuf>,
+    failures: &[(PathBuf, anyhow::Error)],
+) -> Result<()> {
+    let mut mismatches = BTreeMap::new();
+    for (path, e) in failures {
+        mismatches.insert(
+            path.display().to_string(),
+            format!("could not be read: {:#}", e),
+        );
+    }
+
+    let mut parsed = HashSet::new();
+    for file in bundle::parse(&String::from_utf8(bundle)?)? {
+        let reason = match sources.get(&file.path).map(std::fs::read) {
+            Some(Ok(source)) if source == file.contents.as_bytes() => {
+                if parsed.insert(file.path.clone()) {
+                    continue;
+                }
+                "contents do not round-trip".to_string()
+            }
+            Some(Ok(_)) => "contents do not round-trip".to_string(),
+            Some(Err(e)) => format!("could not be read: {}", e),
+            None => "unexpected file reconstructed from contents".to_string(),
+        };
+        mismatches.insert(file.path, reason);
+    }
+    for path in sources.keys() {
+        if !parsed.contains(path) && !mismatches.contains_key(path) {
+            mismatches.insert(path.clone(), "missing from the parsed bundle".to_string());
+        }
+    }
+
+    if mismatches.is_empty() {
+        eprintln!("Validated {} file(s)", sources.len());
+        return Ok(());
+    }
+    for (path, reason) in &mismatches {
+        eprintln!("{}: {}", path, reason);
+    }
+    anyhow::bail!("Validation failed for {} file(s)", mismatches.len())
+}
+
+/// Lists every file under `root` using a parallel walk, sorted by path so
+/// that the order is deterministic and the same as that of the serial walk
+/// with `--deterministic`.
+fn walk_parallel(root: &Path) -> Result<Vec<PathBuf>> {
+    let paths = Mutex::new(Vec::new());
+    let first_error = Mutex::new(None);
+    ignore::WalkBuilder::new(root)
+        .standard_filters(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| match entry {
+                Ok(entry) => {
+                    if entry
+                        .file_type()
+                        .is_some_and(|file_type| file_type.is_file())
+                    {
+                        paths.lock().unwrap().push(entry.into_path());
+                    }
+                    WalkState::Continue
+                }
+                Err(e) => {
+                    first_error.lock().unwrap().get_or_insert(e);
+                    WalkState::Quit
+                }
+            })
+        });
+
+    if let Some(e) = first_error.into_inner().unwrap() {
+        return Err(e).context("Failed to walk the repository");
+    }
+    let mut paths = paths.into_inner().unwrap();
+    paths.sort();
+    Ok(paths)
+}
+
+/// How each bundle, or each chunk of one, is laid out around its files.
+#[derive(Clone, Copy)]
+struct BundleLayout<'a> {
+    preamble: &'a str,
+    compact_headers: bool,
+    file_count_header: bool,
+    path_prefixed_lines: bool,
+}
+
+/// Writes a complete bundle: the preamble, any notes about the run, each file
+/// block, the END marker and an optional summary line after it. Run notes
+/// come before the first header so that they are never read back as part of
+/// a file's contents.
+fn write_bundle(
+    writer: &mut dyn Write,
+    layout: &BundleLayout,
+    blocks: &[&(PathBuf, FileBlock)],
+    notes: &[String],
+    summary: Option<&str>,
+) -> Result<()> {
+    write!(writer, "{}", layout.preamble)?;
+    if layout.file_count_header {
+        let files: usize = blocks
+            .iter()
+            .map(|(path, block)| block_paths(path, block).len())
+            .sum();
+        writeln!(writer, "Files included: {}", files)?;
+    }
+    for note in notes {
+        writeln!(writer, "Note: {}", note)?;
+    }
+
+    let mut current_dir = None;
+    for (relative_file_path, block) in blocks.iter().copied() {
+        write_block(writer, layout, relative_file_path, block, &mut current_dir)?;
+    }
+
+    writeln!(writer, "@@@@END@@@@")?;
+    if let Some(summary) = summary {
+        writeln!(writer, "{}", summary)?;
+    }
+    Ok(())
+}
+
+/// Writes one file block. With compact headers, `current_dir` tracks the
+/// directory whose header was written last, so that each is written once.
+fn write_block(
+    writer: &mut dyn Write,
+    layout: &BundleLayout,
+    relative_file_path: &Path,
+    block: &FileBlock,
+    current_dir: &mut Option<PathBuf>,
+) -> Result<()> {
+    if layout.compact_headers {
+        let dir = relative_file_path.parent().unwrap_or(Path::new(""));
+        if current_dir.as_deref() != Some(dir) {
+            if dir.as_os_str().is_empty() {
+                writeln!(writer, "@@@@./@@@@")?;
+            } else {
+                writeln!(writer, "@@@@{}/@@@@", dir.display())?;
+            }
+            *current_dir = Some(dir.to_path_buf());
+        }
+        let file_name = relative_file_path.file_name().unwrap_or_default();
+        writeln!(writer, "@@@@{}@@@@", Path::new(file_name).display())?;
+    } else {
+        writeln!(writer, "@@@@{}@@@@", relative_file_path.display())?;
+    }
+    if block.merged.is_empty() {
+        return write_block_body(writer, layout, relative_file_path, block);
+    }
+    for (path, merged) in &block.merged {
+        let file_name = path.file_name().unwrap_or_default();
+        writeln!(writer, "--- {} ---", Path::new(file_name).display())?;
+        write_block_body(writer, layout, path, merged)?;
+    }
+    Ok(())
+}
+
+/// Writes the note, symbols and contents of one file, after its header.
+fn write_block_body(
+    writer: &mut dyn Write,
+    layout: &BundleLayout,
+    relative_file_path: &Path,
+    block: &FileBlock,
+) -> Result<()> {
+    for note in &block.notes {
+        writeln!(writer, "Note: {}", note.trim())?;
+    }
+    if !block.symbols.is_empty() {
+        writeln!(writer, "Symbols: {}", block.symbols.join(", "))?;
+    }
+    if layout.path_prefixed_lines {
+        // Like `grep -rn` output: <path>:<line>: <content>
+        for (index, line) in block.contents.lines().enumerate() {
+            writeln!(
+                writer,
+                "{}:{}: {}",
+                relative_file_path.display(),
+                index + 1,
+                line
+            )?;
+        }
+    } else {
+        writeln!(writer, "{}", block.contents)?;
+    }
+    Ok(())
+}
+
+/// The preamble written at the start of a chunk: the full preamble, or with
+/// `--preamble-in first` a continuation line in all chunks but the first.
+fn chunk_preamble<'a>(
+    preamble: &'a str,
+    index: usize,
+    count: usize,
+    preamble_in_first: bool,
+) -> Cow<'a, str> {
+    if index > 0 && preamble_in_first {
+        Cow::Owned(format!("(continued, chunk {}/{})\n", index + 1, count))
+    } else {
+        Cow::Borrowed(preamble)
+    }
+}
+
+/// Roughly estimates how many tokens a model would split text into, at about
+/// four characters per token.
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimates the tokens of a file block as `write_bundle` renders it, with
+/// its headers, note, symbols and any path prefixes.
+fn estimate_block_tokens(
+    layout: &BundleLayout,
+    path: &Path,
+    block: &FileBlock,
+    current_dir: &mut Option<PathBuf>,
+) -> Result<usize> {
+    let mut rendered = Vec::new();
+    write_block(&mut rendered, layout, path, block, current_dir)?;
+    Ok(estimate_tokens(&String::from_utf8_lossy(&rendered)))
+}
+
+/// Estimates the tokens written around the file blocks of each bundle or
+/// chunk: the preamble, the file count, run notes, the END marker and the
+/// summary line.
+fn estimate_overhead_tokens(
+    layout: &BundleLayout,
+    chunks: usize,
+    preamble_in_first: bool,
+    notes: &[String],
+    summary: &str,
+) -> Result<usize> {
+    let mut tokens = 0;
+    for index in 0..chunks {
+        let preamble = chunk_preamble(layout.preamble, index, chunks, preamble_in_first);
+        let chunk_layout = BundleLayout {
+            preamble: &preamble,
+            ..*layout
+        };
+        let chunk_notes = if index == 0 { notes } else { &[] };
+        let chunk_summary = Some(summary).filter(|_| index + 1 == chunks);
+        let mut rendered = Vec::new();
+        write_bundle(
+            &mut rendered,
+            &chunk_layout,
+            &[],
+            chunk_notes,
+            chunk_summary,
+        )?;
+        tokens += estimate_tokens(&String::from_utf8_lossy(&rendered));
+    }
+    Ok(tokens)
+}
+
+/// Combines the blocks of files smaller than `max_bytes` that share a
+/// directory into one block headed `<dir>/*`, where each file starts with a
+/// `--- <name> ---` line. The combined block takes the place of the first
+/// of its files. Directories with a single small file are left alone.
+fn merge_small_blocks(
+    blocks: Vec<(PathBuf, FileBlock)>,
+    max_bytes: u64,
+) -> Vec<(PathBuf, FileBlock)> {
+    let is_small = |block: &FileBlock| (block.contents.len() as u64) < max_bytes;
+    let mut small_per_dir: HashMap<PathBuf, usize> = HashMap::new();
+    for (path, block) in &blocks {
+        if is_small(block) {
+            *small_per_dir
+                .entry(path.parent().unwrap_or(Path::new("")).to_path_buf())
+                .or_default() += 1;
+        }
+    }
+
+    let mut merged: Vec<(PathBuf, FileBlock)> = Vec::with_capacity(blocks.len());
+    let mut merged_index: HashMap<PathBuf, usize> = HashMap::new();
+    for (path, block) in blocks {
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if !is_small(&block) || small_per_dir[&dir] < 2 {
+            merged.push((path, block));
+            continue;
+        }
+
+        match merged_index.get(&dir) {
+            Some(&index) => merged[index].1.merged.push((path, block)),
+            None => {
+                merged_index.insert(dir.clone(), merged.len());
+                let combined = FileBlock {
+                    notes: Vec::new(),
+                    symbols: Vec::new(),
+                    line_endings: None,
+                    non_ascii: 0,
+                    contents: String::new(),
+                    merged: vec![(path, block)],
+                };
+                merged.push((dir.join("*"), combined));
+            }
+        }
+    }
+    merged
+}
+
+/// Writes a JSON object mapping each chunk file to the paths it contains.
+fn write_split_index(
+    index_path: &Path,
+    chunks: &[Vec<&(PathBuf, FileBlock)>],
+    chunk_paths: &[PathBuf],
+) -> Result<()> {
+    let index: serde_json::Map<String, serde_json::Value> = chunks
+        .iter()
+        .zip(chunk_paths)
+        .map(|(chunk, chunk_path)| {
+            let paths = chunk
+                .iter()
+                .flat_map(|(path, block)| block_paths(path, block))
+                .map(|path| path.display().to_string().into())
+                .collect();
+            (
+                chunk_path.display().to_string(),
+                serde_json::Value::Array(paths),
+            )
+        })
+        .collect();
+    let mut writer = BufWriter::new(
+        File::create(index_path)
+            .with_context(|| format!("Could not create split index {}", index_path.display()))?,
+    );
+    serde_json::to_writer_pretty(&mut writer, &index)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Inserts a label before the extension of the output path, naming chunks
+/// and hashed bundles: `bundle.txt` becomes `bundle.1.txt`.
+fn labeled_path(output_path: &Path, label: impl std::fmt::Display) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let file_name = match output_path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, label, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, label),
+    };
+    output_path.with_file_name(file_name)
+}
+
+/// Whether a file is a bundle that `--hash-filename` wrote for this output
+/// path, named with 12 hex digits as in `bundle.<hash>.txt`.
+fn is_hashed_output(output_path: &Path, file_path: &Path) -> bool {
+    if file_path.parent() != output_path.parent() {
+        return false;
+    }
+    let (Some(name), Some(stem)) = (
+        file_path.file_name().and_then(|name| name.to_str()),
+        output_path.file_stem().and_then(|stem| stem.to_str()),
+    ) else {
+        return false;
+    };
+    let rest = name
+        .strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix('.'));
+    let label = match output_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some(extension) => rest
+            .and_then(|rest| rest.strip_suffix(extension))
+            .and_then(|rest| rest.strip_suffix('.')),
+        None => rest,
+    };
+    label.is_some_and(|label| label.len() == 12 && label.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Splits items into `count` chunks of similar total size. The largest items
+/// are placed first, each into the chunk that is currently smallest, and
+/// items keep their original order within a chunk.
+fn balance_chunks<T>(items: &[T], count: usize, size: impl Fn(&T) -> usize) -> Vec<Vec<&T>> {
+    let mut by_size: Vec<usize> = (0..items.len()).collect();
+    by_size.sort_by_key(|&index| std::cmp::Reverse(size(&items[index])));
+
+    let mut totals = vec![0; count];
+    let mut assigned = vec![0; items.len()];
+    for index in by_size {
+        let smallest = (0..count).min_by_key(|&chunk| totals[chunk]).unwrap_or(0);
+        totals[smallest] += size(&items[index]);
+        assigned[index] = smallest;
+    }
+
+    let mut chunks = vec![Vec::new(); count];
+    for (item, chunk) in items.iter().zip(assigned) {
+        chunks[chunk].push(item);
+    }
+    chunks
+}
+
+/// Groups included files by file name, keeping only names used more than
+/// once. Each group is sorted by path.
+fn basename_collisions(blocks: &[(PathBuf, FileBlock)]) -> BTreeMap<&OsStr, Vec<&Path>> {
+    let mut by_name: BTreeMap<&OsStr, Vec<&Path>> = BTreeMap::new();
+    for (path, _) in blocks {
+        if let Some(name) = path.file_name() {
+            by_name.entry(name).or_default().push(path);
+        }
+    }
+    by_name.retain(|_, paths| paths.len() > 1);
+    for paths in by_name.values_mut() {
+        paths.sort();
+    }
+    by_name
+}
+
+fn report_basename_collisions(collisions: &BTreeMap<&OsStr, Vec<&Path>>, style: &Style) {
+    let warning = format!(
+        "Warning: {} file name(s) are shared by several included files:",
+        collisions.len()
+    );
+    eprintln!("{}", style.warning(&warning));
+    for (name, paths) in collisions {
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        eprintln!("  {}: {}", Path::new(name).display(), paths.join(", "));
+    }
+}
+
+/// Lists files whose line endings are CRLF or a mix of CRLF and LF.
+fn report_line_endings(blocks: &[(PathBuf, FileBlock)], style: &Style) {
+    let flagged: Vec<_> = blocks
+        .iter()
+        .filter_map(|(path, block)| match block.line_endings? {
+            LineEndings::Lf => None,
+            LineEndings::Crlf => Some((path, "CRLF")),
+            LineEndings::Mixed => Some((path, "mixed CRLF and LF")),
+        })
+        .collect();
+    if flagged.is_empty() {
+        return;
+    }
+    let heading = format!("{} file(s) do not use LF line endings:", flagged.len());
+    eprintln!("{}", style.warning(&heading));
+    for (path, endings) in flagged {
+        eprintln!("  {}: {}", path.display(), endings);
+    }
+}
+
+/// Summarizes files that could not be bundled. They only fail the run when
+/// `strict` is set.
+fn report_failures(
+    failures: &[(PathBuf, anyhow::Error)],
+    strict: bool,
+    style: &Style,
+) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let heading = format!("{} file(s) could not be bundled:", failures.len());
+    eprintln!("{}", style.error(&heading));
+    for (path, e) in failures {
+        eprintln!("  {}: {:#}", path.display(), e);
+    }
+    if strict {
+        anyhow::bail!("Failed to bundle {} file(s)", failures.len());
+    }
+    Ok(())
+}
+
+/// Opens the output file, or stdout when there is none.
+fn open_output(output_path: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match output_path {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).with_context(|| {
+                format!("Could not create output file {}", path.display())
+            })?))
+        }
+        None => Box::new(BufWriter::new(stdout())),
+    })
+}
+
+/// Parses durations such as `500ms`, `30s` or `2m`. A bare number is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+    let seconds = match unit {
+        "ms" => amount / 1000.0,
+        "" | "s" => amount,
+        "m" => amount * 60.0,
+        "h" => amount * 3600.0,
+        _ => return Err(format!("unknown duration unit '{}' in '{}'", unit, value)),
+    };
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| format!("duration '{}' is out of range", value))
+}
+
+/// Parses a fraction between 0 and 1.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("'{}' is not a number between 0 and 1", value)),
+    }
+}
+
+#[derive(Clone)]
+struct SizeRule {
+    matcher: GlobMatcher,
+    max_bytes: u64,
+}
+
+/// Parses `<GLOB>:<MAX>`, where MAX is a byte count with an optional `k` or
+/// `m` suffix.
+fn parse_size_rule(value: &str) -> Result<SizeRule, String> {
+    let (glob, max) = value
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected <GLOB>:<MAX>, got '{}'", value))?;
+    let matcher = Glob::new(glob)
+        .map_err(|e| format!("invalid glob '{}': {}", glob, e))?
+        .compile_matcher();
+    let (digits, multiplier) = match max.to_ascii_lowercase().chars().last() {
+        Some('k') => (&max[..max.len() - 1], 1024),
+        Some('m') => (&max[..max.len() - 1], 1024 * 1024),
+        _ => (max, 1),
+    };
+    let max_bytes = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|digits| digits.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'", max))?;
+    Ok(SizeRule { matcher, max_bytes })
+}
+
+/// An error that ends the run with a particular exit code rather than 1.
+#[derive(Debug)]
+struct ExitError {
+    code: u8,
+    message: String,
+}
+
+impl std::fmt::Display for ExitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            let code = e.downcast_ref::<ExitError>().map_or(1, |e| e.code);
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn cli() -> Command {
+    Command::new("gprepo")
+        .version("0.1.0")
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("OUTPUT_PATH")
+                .help("Output to path, or - for stdout (default: stdout)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("repo_path")
+                .short('r')
+                .long("repo-path")
+                .value_name("REPO_PATH")
+                .help("Path to the repository")
+                .required(false),
+        )
+        .arg(
+            Arg::new("repo_root")
+                .long("repo-root")
+                .value_name("ROOT_PATH")
+                .help(
+                    "Directory that emitted paths are relative to, overriding repository discovery",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::new("subtree")
+                .long("subtree")
+                .value_name("SUBTREE_PATH")
+                .help("Only walk this directory of the repository (can be specified multiple times)")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("preamble")
+                .short('p')
+                .long("preamble")
+                .value_name("PREAMBLE_PATH")
+                .help("Optional path to the preamble file")
+                .required(false),
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('e')
+                .long("exclude")
+                .value_name("EXCLUDE_PATH")
+                .help("File paths to exclude (supports glob patterns)")
+                .required(false)
+                .num_args(1..)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("include")
+                .short('i')
+                .long("include")
+                .value_name("INCLUDE_PATH")
+                .help("Only process these specific paths (supports glob patterns)")
+                .required(false)
+                .num_args(1..)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("parallel_walk")
+                .long("parallel-walk")
+                .help("Enumerate files with a parallel directory walk")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("with_repo_summary")
+                .long("with-repo-summary")
+                .help("Start the preamble with the default branch, commit and contributor counts, and latest commit")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("with_notes")
+                .long("with-notes")
+                .help("Start the preamble with the git note attached to the checked out commit, if any")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("file_count_header")
+                .long("file-count-header")
+                .help("State how many files the bundle contains right after the preamble")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("compact_headers")
+                .long("compact-headers")
+                .help("Group files by directory and emit each directory header once")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force_include")
+                .long("force-include")
+                .value_name("GLOB")
+                .help("Always include files matching GLOB, overriding every other filter; binary files are base64-encoded")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("fail_on_collision")
+                .long("fail-on-collision")
+                .help("Fail if two included files share a file name")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("from_archive")
+                .long("from-archive")
+                .value_name("ARCHIVE_PATH")
+                .help("Bundle the contents of a .tar.gz archive instead of a repository")
+                .conflicts_with_all(["repo_path", "repo_root"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("path_base")
+                .long("path-base")
+                .value_name("PATH")
+                .help("Emit paths relative to this directory, which must contain the root")
+                .required(false),
+        )
+        .arg(
+            Arg::new("gitignore_rule")
+                .long("gitignore-rule")
+                .value_name("RULE")
+                .help("Additional rule in .gitignore syntax")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("binary_threshold")
+                .long("binary-threshold")
+                .value_name("FRACTION")
+                .help("Treat files as binary when more than this fraction of their first KiB is control characters")
+                .value_parser(parse_fraction)
+                .default_value("0.3"),
+        )
+        .arg(
+            Arg::new("no_gitignore")
+                .long("no-gitignore")
+                .help("Do not skip files matched by .gitignore rules")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("newer_than_file")
+                .long("newer-than-file")
+                .value_name("REFERENCE_PATH")
+                .help("Only include files modified after this file was")
+                .required(false),
+        )
+        .arg(
+            Arg::new("last_author")
+                .long("last-author")
+                .value_name("NAME_OR_EMAIL")
+                .help("Only include files whose most recent commit is by this author")
+                .required(false),
+        )
+        .arg(
+            Arg::new("dirty")
+                .long("dirty")
+                .help("Only include files with uncommitted working-tree changes")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("time_budget")
+                .long("time-budget")
+                .value_name("DURATION")
+                .help("Stop adding files once this much time has passed (e.g. 500ms, 30s, 2m)")
+                .value_parser(parse_duration)
+                .required(false),
+        )
+        .arg(
+            Arg::new("timings")
+                .long("timings")
+                .help("Report the time spent in each phase of the run to stderr")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("eol_report")
+                .long("eol-report")
+                .help("Report files with CRLF or mixed line endings to stderr")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("deterministic")
+                .long("deterministic")
+                .help("Produce byte-identical output for identical inputs on any machine")
+                .conflicts_with("time_budget")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("require_files")
+                .long("require-files")
+                .help("Exit with status 2 instead of writing an empty bundle when no files are included")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("strict_markers")
+                .long("strict-markers")
+                .help("Exit with status 3 instead of warning when file contents contain a bundle marker line")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .help("Color warnings and summaries on stderr")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Exit with an error if any file could not be bundled")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("raw")
+                .long("raw")
+                .help("Emit file contents verbatim, without whitespace reduction or other processing")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("validate")
+                .long("validate")
+                .help("Check that the selected files round-trip through a raw bundle, instead of bundling")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("normalize_unicode")
+                .long("normalize-unicode")
+                .value_name("FORM")
+                .help("Normalize content to this Unicode form and strip zero-width characters")
+                .value_parser(["nfc", "nfkc"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("ascii_only")
+                .long("ascii-only")
+                .value_name("MODE")
+                .help("Replace non-ASCII characters in file contents with ? (the default) or strip them")
+                .value_parser(["replace", "strip"])
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("replace")
+                .required(false),
+        )
+        .arg(
+            Arg::new("sample_ext")
+                .long("sample-ext")
+                .value_name("EXT=N")
+                .help("Include at most N files with this extension, in path order")
+                .value_parser(parse_sample_ext)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("max_files_per_dir")
+                .long("max-files-per-dir")
+                .value_name("N")
+                .help("Include at most N files from any single directory, in path order")
+                .value_parser(clap::value_parser!(usize))
+                .required(false),
+        )
+        .arg(
+            Arg::new("exclude_test_data")
+                .long("exclude-test-data")
+                .help("Skip test fixture directories such as testdata/ and __snapshots__/")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("test_data_dir")
+                .long("test-data-dir")
+                .value_name("DIR_NAME")
+                .help("Treat directories with this name as test data instead of the defaults; may be repeated")
+                .requires("exclude_test_data")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("summarize_lockfiles")
+                .long("summarize-lockfiles")
+                .help("Emit a line with the dependency count of each lockfile instead of its contents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("manifests_first")
+                .long("manifests-first")
+                .help("Emit package manifests such as Cargo.toml and package.json first, unprocessed")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("module_order")
+                .long("module-order")
+                .help("Emit Rust modules after the modules they declare or use")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("entrypoints_first")
+                .long("entrypoints-first")
+                .help("Emit entrypoint files such as main.rs and index.ts before all others")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("entrypoint")
+                .long("entrypoint")
+                .value_name("FILE_NAME")
+                .help("Treat this file name as an entrypoint instead of the defaults; may be repeated")
+                .requires("entrypoints_first")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("tests_after_source")
+                .long("tests-after-source")
+                .help("Emit each test file immediately after the source file it tests")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("repomap")
+                .long("repomap")
+                .help("Emit an outline of each file's top-level symbols before its contents")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("strip_docstrings")
+                .long("strip-docstrings")
+                .help("Remove module, class and function docstrings from Python files")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("size_rule")
+                .long("size-rule")
+                .value_name("GLOB:MAX")
+                .help("Skip files matching GLOB that are larger than MAX bytes (k and m suffixes allowed)")
+                .value_parser(parse_size_rule)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("head_tail")
+                .long("head-tail")
+                .value_name("N")
+                .help("Keep only the first and last N lines of files longer than 2N lines")
+                .value_parser(clap::value_parser!(usize))
+                .required(false),
+        )
+        .arg(
+            Arg::new("max_tokens")
+                .long("max-tokens")
+                .value_name("N")
+                .help("Only include files while the estimated token count of the bundle stays within N")
+                .value_parser(clap::value_parser!(usize))
+                .required(false),
+        )
+        .arg(
+            Arg::new("max_processed_size")
+                .long("max-processed-size")
+                .value_name("BYTES")
+                .help("Skip files whose processed contents are larger than this many bytes")
+                .value_parser(clap::value_parser!(u64))
+                .required(false),
+        )
+        .arg(
+            Arg::new("path_prefixed_lines")
+                .long("path-prefixed-lines")
+                .help("Prefix every content line with its file path and line number, like grep -rn")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("merge_under")
+                .long("merge-under")
+                .value_name("BYTES")
+                .help("Combine files smaller than BYTES in the same directory into one block")
+                .value_parser(clap::value_parser!(u64))
+                .conflicts_with("compact_headers")
+                .required(false),
+        )
+        .arg(
+            Arg::new("skip_empty_files")
+                .long("skip-empty-files")
+                .help("Skip files that are empty or only whitespace after processing")
+                .action(clap::ArgAction::SetTrue)
+                .overrides_with("include_empty_files"),
+        )
+        .arg(
+            Arg::new("include_empty_files")
+                .long("include-empty-files")
+                .help("Emit a block for empty files (the default)")
+                .action(clap::ArgAction::SetTrue)
+                .overrides_with("skip_empty_files"),
+        )
+        .arg(
+            Arg::new("signatures_only")
+                .long("signatures-only")
+                .help("Replace function bodies with { ... } in Rust, TypeScript and Go files")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("progressive")
+                .long("progressive")
+                .help("Include files in the focus directory in full, those one directory away as signatures, and only the paths of the rest")
+                .requires("focus")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("focus")
+                .long("focus")
+                .value_name("PATH")
+                .help("File or directory that --progressive measures distances from")
+                .requires("progressive")
+                .required(false),
+        )
+        .arg(
+            Arg::new("notebook_include_outputs")
+                .long("notebook-include-outputs")
+                .help("Include the text outputs of Jupyter notebook cells")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("cache_dir")
+                .long("cache-dir")
+                .value_name("CACHE_DIR")
+                .help("Reuse processed file contents cached in this directory")
+                .required(false),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("MANIFEST_PATH")
+                .help("Write a manifest of the content hash of each bundled file")
+                .required(false),
+        )
+        .arg(
+            Arg::new("manifest_only")
+                .long("manifest-only")
+                .value_name("PREVIOUS_MANIFEST")
+                .help("Only print the paths added, modified or removed since a previous manifest")
+                .required(false),
+        )
+        .arg(
+            Arg::new("chunks")
+                .long("chunks")
+                .value_name("N")
+                .help("Split the bundle into N self-contained files of similar size next to the output file")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .conflicts_with_all(["manifest_only", "validate"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("hash_filename")
+                .long("hash-filename")
+                .help("Insert a hash of the bundle into the output file name")
+                .conflicts_with_all(["chunks", "manifest_only", "validate"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("preamble_in")
+                .long("preamble-in")
+                .value_name("CHUNKS")
+                .help("Write the preamble in all chunks, or only the first with a short continuation line in the rest")
+                .value_parser(["all", "first"])
+                .default_value("all"),
+        )
+        .arg(
+            Arg::new("split_index")
+                .long("split-index")
+                .value_name("INDEX_PATH")
+                .help("Write a JSON index of the files in each chunk")
+                .requires("chunks")
+                .required(false),
+        )
+        .subcommand(
+            Command::new("unbundle")
+                .about("Write the files of a bundle back to disk")
+                .arg(
+                    Arg::new("bundle")
+                        .value_name("BUNDLE")
+                        .help("Path to the bundle")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("out_dir")
+                        .value_name("OUTDIR")
+                        .help("Directory to write the files under")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("cost")
+                .about("Estimate the tokens in a bundle and what sending it would cost")
+                .arg(
+                    Arg::new("bundle")
+                        .value_name("BUNDLE")
+                        .help("Path to the bundle, or - to read it from stdin")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("pricing")
+                        .long("pricing")
+                        .value_name("PRICING_PATH")
+                        .help("File of `<model> <dollars per million tokens>` lines to use instead of the built-in prices")
+                        .required(false),
+                ),
+        )
+}
+
+fn run() -> Result<()> {
+    let matches = cli().get_matches();
+
+    if let Some(("unbundle", unbundle_matches)) = matches.subcommand() {
+        let bundle_path = unbundle_matches.get_one::<String>("bundle").unwrap();
+        let out_dir = unbundle_matches.get_one::<String>("out_dir").unwrap();
+        let count = bundle::unbundle(Path::new(bundle_path), Path::new(out_dir))?;
+        eprintln!("Wrote {} file(s) to {}", count, out_dir);
+        return Ok(());
+    }
+
+    if let Some(("cost", cost_matches)) = matches.subcommand() {
+        let bundle_path = cost_matches.get_one::<String>("bundle").unwrap();
+        let pricing_path = cost_matches.get_one::<String>("pricing").map(Path::new);
+        return cost::run(bundle_path, pricing_path);
+    }
+
+    bundle_repository(&matches)
+}
+
+/// Bundles a repository. Each phase is a function of its own, called here in
+/// the order the pipeline runs: resolve the repository and root, walk it,
+/// filter the walked files, cap and order the rest, read them into blocks,
+/// apply the token budget, check the blocks, merge small ones and write the
+/// bundle.
+fn bundle_repository(matches: &ArgMatches) -> Result<()> {
+    let style = Style::new(matches.get_one::<String>("color").unwrap());
+    let compact_headers = matches.get_flag("compact_headers");
+    // Validation bundles raw so that files can be compared with their source
+    let validate = matches.get_flag("validate");
+    let process_options = ProcessOptions {
+        raw: matches.get_flag("raw") || validate,
+        strip_docstrings: matches.get_flag("strip_docstrings"),
+        signatures_only: matches.get_flag("signatures_only"),
+        notebook_outputs: matches.get_flag("notebook_include_outputs"),
+        head_tail: matches.get_one::<usize>("head_tail").copied(),
+        normalize_unicode: matches.get_one::<String>("normalize_unicode").map(|form| {
+            match form.as_str() {
+                "nfkc" => UnicodeForm::Nfkc,
+                _ => UnicodeForm::Nfc,
+            }
+        }),
+    };
+    let process_start_time = SystemTime::now();
+    let process_start_instant = Instant::now();
+
+    let repo_root = match matches.get_one::<String>("repo_root") {
+        Some(path) => Some(
+            Path::new(path)
+                .canonicalize()
+                .with_context(|| format!("Could not find repository root {}", path))?,
+        ),
+        None => None,
+    };
+    let archive = match matches.get_one::<String>("from_archive") {
+        Some(path) => Some(archive::ExtractedArchive::extract(Path::new(path))?),
+        None => None,
+    };
+    let repo = open_repository(matches, archive.as_ref(), repo_root.as_deref())?;
+    let repo_path = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("Could not find repository working directory"))?
+        .canonicalize()?;
+    let repo_path = repo_path.as_path();
+
+    // Emitted paths are relative to the root, while ignore rules and history
+    // are resolved relative to the repository working directory.
+    if let Some(rules) = matches.get_many::<String>("gitignore_rule") {
+        let rules: Vec<&str> = rules.map(String::as_str).collect();
+        repo.add_ignore_rule(&rules.join("\n"))
+            .context("Failed to add gitignore rules")?;
+    }
+
+    let root = repo_root.as_deref().unwrap_or(repo_path);
+    if !root.starts_with(repo_path) {
+        anyhow::bail!(
+            "Repository root {} is outside the repository at {}",
+            root.display(),
+            repo_path.display()
+        );
+    }
+    let path_base = resolve_path_base(matches, root)?;
+    let focus_dir = resolve_focus_dir(matches, root)?;
+
+    let mut _gitignore = repo
+        .statuses(Some(
+            StatusOptions::new()
+                .include_ignored(false)
+                .show(StatusShow::IndexAndWorkdir),
+        ))
+        .context("Failed to read gitignore")?;
+
+    let outputs = OutputPaths::new(matches)?;
+    let cache_dir = match matches.get_one::<String>("cache_dir") {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).context("Could not create cache directory")?;
+            Some(Path::new(dir).canonicalize()?)
+        }
+        None => None,
+    };
+    let manifest_path = matches.get_one::<String>("manifest");
+    let previous_manifest = match matches.get_one::<String>("manifest_only") {
+        Some(path) => Some(read_manifest(Path::new(path))?),
+        None => None,
+    };
+    let preamble = read_preamble(matches, &repo, compact_headers, previous_manifest.is_some())?;
+
+    let strict = matches.get_flag("strict");
+    let mut failures: Vec<(PathBuf, anyhow::Error)> = Vec::new();
+    let mut timings = Timings::default();
+
+    let walk_started = Instant::now();
+    let candidates = walk_files(&walk_roots(matches, root)?, matches, compact_headers)?;
+    timings.walking = walk_started.elapsed();
+
+    let filters = Filters::new(
+        matches,
+        &repo,
+        &outputs,
+        cache_dir.as_deref(),
+        process_start_time,
+    )?;
+    let filter_started = Instant::now();
+    let Selection {
+        mut files,
+        forced,
+        forced_binary,
+        lockfile_summaries,
+        gitignored_count,
+    } = select_files(
+        &candidates,
+        &filters,
+        &repo,
+        repo_path,
+        root,
+        &mut timings,
+        &mut failures,
+    )?;
+    timings.filtering = filter_started
+        .elapsed()
+        .saturating_sub(timings.binary_detection);
+
+    let (sampled_out, capped_dirs) = cap_files(matches, &mut files, &forced);
+    let files = order_files(matches, root, files, compact_headers);
+    if let Some(path_base) = &path_base {
+        check_emitted_paths(&files, root, path_base)?;
+    }
+
+    let read_options = ReadOptions {
+        block: BlockOptions {
+            cache_dir: cache_dir.as_deref(),
+            repomap: matches.get_flag("repomap"),
+            line_endings: matches.get_flag("eol_report"),
+            ascii_only: matches
+                .get_one::<String>("ascii_only")
+                .map(|mode| match mode.as_str() {
+                    "strip" => AsciiMode::Strip,
+                    _ => AsciiMode::Replace,
+                }),
+            process: &process_options,
+        },
+        root,
+        path_base: path_base.as_deref(),
+        focus_dir: focus_dir.as_deref(),
+        forced: &forced,
+        forced_binary: &forced_binary,
+        manifests_first: matches.get_flag("manifests_first"),
+        max_processed_size: matches.get_one::<u64>("max_processed_size").copied(),
+        skip_empty_files: matches.get_flag("skip_empty_files"),
+        time_budget: matches.get_one::<Duration>("time_budget").copied(),
+        started: process_start_instant,
+        keep_sources: validate,
+        hash_files: manifest_path.is_some() || previous_manifest.is_some(),
+    };
+    let ReadBlocks {
+        mut blocks,
+        mut sources,
+        mut manifest,
+        forced_blocks,
+        unbundled_by_time,
+    } = read_blocks(&files, &read_options, &mut timings, &mut failures);
+
+    let notes = run_notes(
+        unbundled_by_time,
+        &capped_dirs,
+        &sampled_out,
+        lockfile_summaries,
+    );
+    let layout = BundleLayout {
+        preamble: &preamble,
+        compact_headers,
+        file_count_header: matches.get_flag("file_count_header"),
+        path_prefixed_lines: matches.get_flag("path_prefixed_lines"),
+    };
+    let preamble_in_first = matches.get_one::<String>("preamble_in").unwrap() == "first";
+    let mut token_summary = None;
+    if let Some(&max_tokens) = matches.get_one::<usize>("max_tokens") {
+        let (kept, summary) = apply_token_budget(
+            blocks,
+            max_tokens,
+            &layout,
+            outputs.chunks,
+            preamble_in_first,
+            &notes,
+            &forced_blocks,
+        )?;
+        blocks = kept;
+        token_summary = Some(summary);
+    }
+
+    // The manifest and validation cover exactly the files that made it into
+    // the bundle
+    if !manifest.is_empty() || !sources.is_empty() {
+        let bundled: HashSet<String> = blocks
+            .iter()
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        manifest.retain(|path, _| bundled.contains(path));
+        sources.retain(|path, _| bundled.contains(path));
+    }
+    if let Some(previous_manifest) = previous_manifest {
+        let mut writer = open_output(outputs.output_path.as_deref())?;
+        write_manifest_changes(&mut writer, &previous_manifest, &manifest)?;
+        writer.flush()?;
+        return report_failures(&failures, strict, &style);
+    }
+
+    let merge_under = matches.get_one::<u64>("merge_under").copied();
+    check_blocks(&blocks, matches, merge_under.is_some(), &style)?;
+    if let Some(max_bytes) = merge_under {
+        blocks = merge_small_blocks(blocks, max_bytes);
+    }
+
+    if validate {
+        let mut buffer = Vec::new();
+        let all: Vec<_> = blocks.iter().collect();
+        write_bundle(&mut buffer, &layout, &all, &notes, token_summary.as_deref())?;
+        return validate_round_trip(buffer, &sources, &failures);
+    }
+
+    let write_started = Instant::now();
+    if outputs.chunks.is_none() {
+        // The output is only created once every check has passed, so that a
+        // refused run leaves an existing file alone
+        let mut writer = open_output(outputs.output_path.as_deref())?;
+        let all: Vec<_> = blocks.iter().collect();
+        write_bundle(&mut writer, &layout, &all, &notes, token_summary.as_deref())?;
+        writer.flush()?;
+    } else {
+        let chunk_layout = ChunkLayout {
+            layout,
+            preamble_in_first,
+            notes: &notes,
+            summary: token_summary.as_deref(),
+        };
+        let split_index = matches.get_one::<String>("split_index").map(Path::new);
+        write_chunks(&blocks, &chunk_layout, &outputs.chunk_paths, split_index)?;
+    }
+    timings.writing = write_started.elapsed();
+
+    // The bundle is written to the output path first and then renamed
+    if let Some(output_path) = outputs
+        .output_path
+        .as_ref()
+        .filter(|_| outputs.hash_filename)
+    {
+        let hash = Oid::hash_file(ObjectType::Blob, output_path)?.to_string();
+        let hashed_path = labeled_path(output_path, &hash[..12]);
+        std::fs::rename(output_path, &hashed_path)
+            .with_context(|| format!("Could not rename bundle to {}", hashed_path.display()))?;
+        eprintln!("Wrote {}", hashed_path.display());
+    }
+
+    if let Some(manifest_path) = manifest_path {
+        let mut manifest_writer = BufWriter::new(File::create(manifest_path)?);
+        write_manifest(&mut manifest_writer, &manifest)?;
+    }
+    if matches.get_flag("timings") {
+        timings.report(&style);
+    }
+
+    if blocks.is_empty() && gitignored_count > 0 {
+        let hint = format!(
+            "No files were included, and .gitignore rules skipped {} file(s). Pass --no-gitignore to include them.",
+            gitignored_count
+        );
+        eprintln!("{}", style.warning(&hint));
+    }
+    report_failures(&failures, strict, &style)
+}
+
+/// Opens the repository to bundle: an extracted archive, the one containing
+/// `--repo-path` or the root, or else the one containing the current
+/// directory.
+fn open_repository(
+    matches: &ArgMatches,
+    archive: Option<&archive::ExtractedArchive>,
+    repo_root: Option<&Path>,
+) -> Result<Repository> {
+    Ok(
+        match (archive, matches.get_one::<String>("repo_path"), repo_root) {
+            (Some(archive), _, _) => {
+                Repository::open(archive.path()).context("Could not open extracted archive")?
+            }
+            (None, Some(path), _) => {
+                Repository::discover(path).context("Could not find repository")?
+            }
+            (None, None, Some(root)) => {
+                Repository::discover(root).context("Could not find repository")?
+            }
+            (None, None, None) => {
+                let current_dir = std::env::current_dir()?;
+                Repository::discover(current_dir).context("Could not find repository")?
+            }
+        },
+    )
+}
+
+/// Resolves `--path-base`, which may sit above or below the root.
+fn resolve_path_base(matches: &ArgMatches, root: &Path) -> Result<Option<PathBuf>> {
+    let Some(base) = matches.get_one::<String>("path_base") else {
+        return Ok(None);
+    };
+    let base = Path::new(base)
+        .canonicalize()
+        .with_context(|| format!("Could not find path base {}", base))?;
+    if !root.starts_with(&base) && !base.starts_with(root) {
+        anyhow::bail!(
+            "Path base {} neither contains nor is inside {}",
+            base.display(),
+            root.display()
+        );
+    }
+    Ok(Some(base))
+}
+
+/// The path a file is emitted with: stripped against the path base, or
+/// relative to the root for files outside a base below the root.
+fn emitted_path(root: &Path, path_base: Option<&Path>, relative_file_path: &Path) -> PathBuf {
+    let file_path = root.join(relative_file_path);
+    match path_base.and_then(|base| file_path.strip_prefix(base).ok()) {
+        Some(path) => path.to_path_buf(),
+        None => relative_file_path.to_path_buf(),
+    }
+}
+
+/// Fails if two files would be emitted with the same path. Files outside a
+/// path base below the root keep their root-relative paths, which can
+/// coincide with the stripped path of a file inside it.
+fn check_emitted_paths(files: &[PathBuf], root: &Path, path_base: &Path) -> Result<()> {
+    let mut emitted: HashMap<PathBuf, &Path> = HashMap::new();
+    for file in files {
+        let path = emitted_path(root, Some(path_base), file);
+        if let Some(other) = emitted.insert(path.clone(), file) {
+            anyhow::bail!(
+                "{} and {} would both be emitted as {}; choose a --path-base that contains them both",
+                other.display(),
+                file.display(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `--focus` to a directory relative to the root. Distances are
+/// measured between directories, so a focus file stands for the directory
+/// holding it.
+fn resolve_focus_dir(matches: &ArgMatches, root: &Path) -> Result<Option<PathBuf>> {
+    let Some(focus) = matches.get_one::<String>("focus") else {
+        return Ok(None);
+    };
+    let path = Path::new(focus)
+        .canonicalize()
+        .with_context(|| format!("Could not find focus path {}", focus))?;
+    let dir = if path.is_dir() {
+        path.as_path()
+    } else {
+        path.parent().unwrap_or(&path)
+    };
+    let dir = dir.strip_prefix(root).map_err(|_| {
+        anyhow::anyhow!(
+            "Focus path {} is outside {}",
+            path.display(),
+            root.display()
+        )
+    })?;
+    Ok(Some(dir.to_path_buf()))
+}
+
+/// Where the bundle is written, and the absolute paths of gprepo's own output
+/// files so that a later run does not bundle them.
+struct OutputPaths {
+    output_path: Option<PathBuf>,
+    chunks: Option<usize>,
+    chunk_paths: Vec<PathBuf>,
+    hash_filename: bool,
+    resolved_output: Option<PathBuf>,
+    resolved_chunks: Vec<PathBuf>,
+}
+
+impl OutputPaths {
+    fn new(matches: &ArgMatches) -> Result<OutputPaths> {
+        // `-o -` writes to stdout like no `-o` at all
+        let output_path: Option<PathBuf> = matches
+            .get_one::<String>("output")
+            .filter(|path| path.as_str() != "-")
+            .map(PathBuf::from);
+        let chunks = matches.get_one::<usize>("chunks").copied();
+        let chunk_paths: Vec<PathBuf> = match (chunks, &output_path) {
+            (Some(count), Some(output_path)) => (1..=count)
+                .map(|index| labeled_path(output_path, index))
+                .collect(),
+            (Some(_), None) => anyhow::bail!("--chunks needs an output file given with -o"),
+            (None, _) => Vec::new(),
+        };
+        let hash_filename = matches.get_flag("hash_filename");
+        if hash_filename && output_path.is_none() {
+            anyhow::bail!("--hash-filename needs an output file given with -o");
+        }
+        // Output files are matched against walked files by absolute path,
+        // since the output path may be relative to the current directory
+        let resolved_output = output_path.as_ref().and_then(|path| {
+            let dir = path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            Some(dir.canonicalize().ok()?.join(path.file_name()?))
+        });
+        let resolved_chunks: Vec<PathBuf> = match (chunks, &resolved_output) {
+            (Some(count), Some(output)) => (1..=count)
+                .map(|index| labeled_path(output, index))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(OutputPaths {
+            output_path,
+            chunks,
+            chunk_paths,
+            hash_filename,
+            resolved_output,
+            resolved_chunks,
+        })
+    }
+
+    /// Whether a walked file is the bundle, a chunk or a hashed bundle that
+    /// this run writes or an earlier one like it wrote.
+    fn is_own_output(&self, file_path: &Path) -> bool {
+        self.resolved_output.as_ref().is_some_and(|output| {
+            output == file_path
+                || self.resolved_chunks.iter().any(|chunk| chunk == file_path)
+                || (self.hash_filename && is_hashed_output(output, file_path))
+        })
+    }
+}
+
+/// Builds the preamble: the `--preamble` file or the default description of
+/// the format, after any repository summary and git notes. It is empty when
+/// only a report is written, as `--manifest-only` does.
+fn read_preamble(
+    matches: &ArgMatches,
+    repo: &Repository,
+    compact_headers: bool,
+    report_only: bool,
+) -> Result<String> {
+    let mut preamble = String::new();
+    if report_only {
+        return Ok(preamble);
+    } else if let Some(preamble_path) = matches.get_one::<String>("preamble") {
+        File::open(preamble_path)?.read_to_string(&mut preamble)?;
+        preamble.push('\n');
+    } else {
+        preamble.push_str("Below is a repository containing files. Each file begins with @@@@<file-path>@@@@ followed by its content. The repository ends with @@@@END@@@@. After this marker, instructions related to the repository are provided.\n");
+        if compact_headers {
+            preamble.push_str("A header ending in / such as @@@@src/@@@@ names a directory; the file headers that follow it are relative to that directory.\n");
+        }
+    }
+
+    if matches.get_flag("with_notes") {
+        if let Some(notes) = head_notes(repo).context("Failed to read git notes")? {
+            preamble.insert_str(0, &notes);
+        }
+    }
+
+    if matches.get_flag("with_repo_summary") {
+        let summary = repo_summary(repo).context("Failed to summarize repository")?;
+        preamble.insert_str(0, &summary);
+    }
+    Ok(preamble)
+}
+
+/// The directories to walk: the root, or each `--subtree`. Nested subtrees
+/// are covered by their ancestors, so they are dropped to avoid walking them
+/// twice.
+fn walk_roots(matches: &ArgMatches, root: &Path) -> Result<Vec<PathBuf>> {
+    let Some(subtrees) = matches.get_many::<String>("subtree") else {
+        return Ok(vec![root.to_path_buf()]);
+    };
+    let mut subtrees = subtrees
+        .map(|subtree| {
+            let path = root
+                .join(subtree)
+                .canonicalize()
+                .with_context(|| format!("Could not find subtree {}", subtree))?;
+            if !path.starts_with(root) {
+                anyhow::bail!("Subtree {} is outside the repository", subtree);
+            }
+            Ok(path)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    subtrees.sort();
+    let mut walk_roots: Vec<PathBuf> = Vec::new();
+    for subtree in subtrees {
+        if !walk_roots.iter().any(|kept| subtree.starts_with(kept)) {
+            walk_roots.push(subtree);
+        }
+    }
+    Ok(walk_roots)
+}
+
+/// Lists every file under the walk roots, outside `.git`.
+///
+/// Directory listings come back in whatever order the filesystem keeps
+/// them, which is the only input to the output order that varies across
+/// machines, so `--deterministic` sorts them by name. The time budget and
+/// the check for files modified during the run also depend on the clock, and
+/// are refused or skipped with that flag.
+fn walk_files(
+    walk_roots: &[PathBuf],
+    matches: &ArgMatches,
+    compact_headers: bool,
+) -> Result<Vec<PathBuf>> {
+    let mut candidates = Vec::new();
+    for walk_root in walk_roots {
+        if matches.get_flag("parallel_walk") {
+            candidates.extend(walk_parallel(walk_root)?);
+            continue;
+        }
+
+        // Compact headers rely on every file of a directory being emitted
+        // together, so files are sorted ahead of subdirectories.
+        let mut walker = WalkDir::new(walk_root);
+        if compact_headers {
+            walker = walker.sort_by(|a, b| {
+                a.file_type()
+                    .is_dir()
+                    .cmp(&b.file_type().is_dir())
+                    .then_with(|| a.file_name().cmp(b.file_name()))
+            });
+        } else if matches.get_flag("deterministic") {
+            walker = walker.sort_by_file_name();
+        }
+        for entry in walker
+            .into_iter()
+            .filter_entry(|entry| entry.file_name() != ".git")
+        {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                candidates.push(entry.into_path());
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// Everything that decides whether a walked file is bundled.
+struct Filters<'a> {
+    outputs: &'a OutputPaths,
+    cache_dir: Option<&'a Path>,
+    /// Files modified at or after this time are skipped, unless the run is
+    /// deterministic, since that depends on the clock.
+    started: Option<SystemTime>,
+    force_include: GlobSet,
+    excludes: Vec<&'a String>,
+    includes: Option<Vec<&'a String>>,
+    exclude_set: GlobSet,
+    lockfile_exclude: GlobMatcher,
+    summarize_lockfiles: bool,
+    test_data_dirs: Vec<&'a OsStr>,
+    no_gitignore: bool,
+    last_author: Option<&'a String>,
+    authors: HashMap<PathBuf, Author>,
+    dirty: Option<HashSet<PathBuf>>,
+    size_rules: Vec<SizeRule>,
+    newer_than: Option<SystemTime>,
+    binary_threshold: f64,
+}
+
+impl<'a> Filters<'a> {
+    fn new(
+        matches: &'a ArgMatches,
+        repo: &Repository,
+        outputs: &'a OutputPaths,
+        cache_dir: Option<&'a Path>,
+        started: SystemTime,
+    ) -> Result<Filters<'a>> {
+        let last_author = matches.get_one::<String>("last_author");
+        let authors = match last_author {
+            Some(_) => last_authors(repo).context("Failed to read commit authors")?,
+            None => HashMap::new(),
+        };
+        let dirty = if matches.get_flag("dirty") {
+            Some(dirty_paths(repo).context("Failed to read working tree status")?)
+        } else {
+            None
+        };
+
+        let exclude_set = {
+            let mut builder = GlobSetBuilder::new();
+            if let Some(exclude_paths) = matches.get_many::<String>("exclude") {
+                for path in exclude_paths {
+                    builder.add(path.parse().unwrap());
+                }
+            }
+            // Add default patterns
+            builder.add("*changelog*".parse().unwrap());
+            builder.add("*CHANGELOG*".parse().unwrap());
+            builder.add(".github*".parse().unwrap());
+            builder.add(".gitignore".parse().unwrap());
+            builder.add("gprepo".parse().unwrap());
+            builder.add("*LICENSE*".parse().unwrap());
+            builder.add("*README*".parse().unwrap());
+            builder.build().unwrap()
+        };
+
+        // A missing reference file means nothing has been bundled yet
+        let newer_than = match matches.get_one::<String>("newer_than_file") {
+            Some(reference) => match std::fs::metadata(reference) {
+                Ok(metadata) => Some(metadata.modified()?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e).context("Could not read reference file"),
+            },
+            None => None,
+        };
+
+        let force_include = {
+            let mut builder = GlobSetBuilder::new();
+            for glob in matches
+                .get_many::<String>("force_include")
+                .into_iter()
+                .flatten()
+            {
+                builder.add(Glob::new(glob).with_context(|| format!("Invalid glob {}", glob))?);
+            }
+            builder.build()?
+        };
+        let test_data_dirs: Vec<&OsStr> = if matches.get_flag("exclude_test_data") {
+            match matches.get_many::<String>("test_data_dir") {
+                Some(names) => names.map(OsStr::new).collect(),
+                None => DEFAULT_TEST_DATA_DIRS.iter().map(OsStr::new).collect(),
+            }
+        } else {
+            Vec::new()
+        };
+
+        Ok(Filters {
+            outputs,
+            cache_dir,
+            started: Some(started).filter(|_| !matches.get_flag("deterministic")),
+            force_include,
+            excludes: matches
+                .get_many::<String>("exclude")
+                .into_iter()
+                .flatten()
+                .collect(),
+            includes: matches
+                .get_many::<String>("include")
+                .map(|includes| includes.collect()),
+            exclude_set,
+            // Kept apart from the other default patterns so that lockfiles
+            // can still be summarized
+            lockfile_exclude: Glob::new("*.lock").unwrap().compile_matcher(),
+            summarize_lockfiles: matches.get_flag("summarize_lockfiles"),
+            test_data_dirs,
+            no_gitignore: matches.get_flag("no_gitignore"),
+            last_author,
+            authors,
+            dirty,
+            size_rules: matches
+                .get_many::<SizeRule>("size_rule")
+                .map(|rules| rules.cloned().collect())
+                .unwrap_or_default(),
+            newer_than,
+            binary_threshold: *matches.get_one::<f64>("binary_threshold").unwrap(),
+        })
+    }
+}
+
+/// The files that passed the filters, relative to the root.
+struct Selection {
+    files: Vec<PathBuf>,
+    forced: HashSet<PathBuf>,
+    forced_binary: HashSet<PathBuf>,
+    lockfile_summaries: Vec<String>,
+    gitignored_count: usize,
+}
+
+/// Filters the walked files down to those to bundle. Files that cannot be
+/// checked are recorded in `failures`.
+fn select_files(
+    candidates: &[PathBuf],
+    filters: &Filters,
+    repo: &Repository,
+    repo_path: &Path,
+    root: &Path,
+    timings: &mut Timings,
+    failures: &mut Vec<(PathBuf, anyhow::Error)>,
+) -> Result<Selection> {
+    let mut selection = Selection {
+        files: Vec::new(),
+        forced: HashSet::new(),
+        forced_binary: HashSet::new(),
+        lockfile_summaries: Vec::new(),
+        gitignored_count: 0,
+    };
+    for file_path in candidates {
+        let file_path = file_path.as_path();
+        let relative_file_path = file_path.strip_prefix(root).unwrap();
+        let repo_relative_path = file_path.strip_prefix(repo_path).unwrap();
+        let path_str = relative_file_path.to_str().unwrap_or("");
+
+        // gprepo's own output and cache, and anything written since the run
+        // started, are skipped even when forced.
+        if filters.outputs.is_own_output(file_path) {
+            continue;
+        }
+        if filters
+            .cache_dir
+            .is_some_and(|dir| file_path.starts_with(dir))
+        {
+            continue;
+        }
+        let metadata = match file_path.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                failures.push((relative_file_path.to_path_buf(), e.into()));
+                continue;
+            }
+        };
+        let modified_time = metadata.modified().ok();
+        if let (Some(modified_time), Some(started)) = (modified_time, filters.started) {
+            if modified_time >= started {
+                continue;
+            }
+        }
+
+        // Forced files skip every other filter, but binary ones still need to
+        // be known so that they can be encoded
+        if filters.force_include.is_match(path_str) {
+            timings.binary_scans += 1;
+            match is_binary(file_path, filters.binary_threshold) {
+                Ok(true) => {
+                    selection
+                        .forced_binary
+                        .insert(relative_file_path.to_path_buf());
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    failures.push((relative_file_path.to_path_buf(), e));
+                    continue;
+                }
+            }
+            selection.forced.insert(relative_file_path.to_path_buf());
+        } else {
+            let should_exclude = filters
+                .excludes
+                .iter()
+                .any(|exclude_path| is_child_of(path_str, exclude_path));
+            let should_include = filters.includes.as_ref().is_none_or(|includes| {
+                includes
+                    .iter()
+                    .any(|include_path| is_child_of(path_str, include_path))
+            });
+            if should_exclude || !should_include {
+                continue;
+            }
+
+            let lockfile_name = file_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| filters.summarize_lockfiles && lockfile::is_lockfile(name));
+            if filters.exclude_set.is_match(path_str)
+                || (lockfile_name.is_none() && filters.lockfile_exclude.is_match(path_str))
+            {
+                continue;
+            }
+
+            let in_test_data = relative_file_path.parent().is_some_and(|dir| {
+                dir.components()
+                    .any(|component| filters.test_data_dirs.contains(&component.as_os_str()))
+            });
+            if in_test_data {
+                continue;
+            }
+
+            // Sidecar notes are emitted alongside the file they annotate, so
+            // only those without one are bundled as files
+            let annotated = path_str
+                .strip_suffix(SIDECAR_SUFFIX)
+                .is_some_and(|annotated| root.join(annotated).is_file());
+            if annotated {
+                continue;
+            }
+
+            let should_ignore = !filters.no_gitignore
+                && repo.status_should_ignore(repo_relative_path).map_err(|e| {
+                    io::Error::other(format!(
+                        "Failed to check if path should be ignored: {:?}",
+                        e
+                    ))
+                })?;
+            if should_ignore {
+                selection.gitignored_count += 1;
+                continue;
+            }
+
+            if let Some(last_author) = filters.last_author {
+                let matches_author = filters
+                    .authors
+                    .get(repo_relative_path)
+                    .is_some_and(|author| author.matches(last_author));
+                if !matches_author {
+                    continue;
+                }
+            }
+
+            if filters
+                .dirty
+                .as_ref()
+                .is_some_and(|dirty| !dirty.contains(repo_relative_path))
+            {
+                continue;
+            }
+
+            // Lockfiles that pass the filters above are summarized rather
+            // than bundled
+            if let Some(file_name) = lockfile_name {
+                let count = std::fs::read_to_string(file_path)
+                    .ok()
+                    .and_then(|contents| lockfile::dependency_count(file_name, &contents));
+                if let Some(count) = count {
+                    let noun = if count == 1 {
+                        "dependency"
+                    } else {
+                        "dependencies"
+                    };
+                    selection
+                        .lockfile_summaries
+                        .push(format!("{}: {} {}", path_str, count, noun));
+                }
+                continue;
+            }
+
+            let too_large = filters
+                .size_rules
+                .iter()
+                .any(|rule| rule.matcher.is_match(path_str) && metadata.len() > rule.max_bytes);
+            if too_large {
+                continue;
+            }
+
+            if let (Some(modified_time), Some(reference)) = (modified_time, filters.newer_than) {
+                if modified_time <= reference {
+                    continue;
+                }
+            }
+
+            // Binary detection opens and reads the file, so it runs only once
+            // every cheaper filter above has let the file through.
+            let binary_started = Instant::now();
+            let binary = is_binary(file_path, filters.binary_threshold);
+            timings.binary_scans += 1;
+            timings.binary_detection += binary_started.elapsed();
+            match binary {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => {
+                    failures.push((relative_file_path.to_path_buf(), e));
+                    continue;
+                }
+            }
+        }
+
+        selection.files.push(relative_file_path.to_path_buf());
+    }
+    Ok(selection)
+}
+
+/// Applies `--sample-ext` and `--max-files-per-dir`, which never drop forced
+/// files, returning how many files each dropped per extension and per
+/// directory.
+fn cap_files(
+    matches: &ArgMatches,
+    files: &mut Vec<PathBuf>,
+    forced: &HashSet<PathBuf>,
+) -> (BTreeMap<String, usize>, BTreeMap<PathBuf, usize>) {
+    let sample_limits: HashMap<String, usize> = matches
+        .get_many::<(String, usize)>("sample_ext")
+        .map(|limits| limits.cloned().collect())
+        .unwrap_or_default();
+    let sampled_out = if sample_limits.is_empty() {
+        BTreeMap::new()
+    } else {
+        cap_per_group(files, |file| {
+            if forced.contains(file) {
+                return None;
+            }
+            let extension = file.extension()?.to_str()?;
+            Some((extension.to_string(), *sample_limits.get(extension)?))
+        })
+    };
+
+    let capped_dirs = match matches.get_one::<usize>("max_files_per_dir") {
+        Some(&max) => cap_per_group(files, |file| {
+            if forced.contains(file) {
+                return None;
+            }
+            Some((file.parent().unwrap_or(Path::new("")).to_path_buf(), max))
+        }),
+        None => BTreeMap::new(),
+    };
+    (sampled_out, capped_dirs)
+}
+
+/// Puts the files in bundle order, applying each ordering flag in turn.
+fn order_files(
+    matches: &ArgMatches,
+    root: &Path,
+    mut files: Vec<PathBuf>,
+    compact_headers: bool,
+) -> Vec<PathBuf> {
+    if matches.get_flag("module_order") {
+        files = module_order::order(root, &files);
+    }
+    if matches.get_flag("entrypoints_first") {
+        let entrypoints: Vec<&str> = match matches.get_many::<String>("entrypoint") {
+            Some(names) => names.map(String::as_str).collect(),
+            None => DEFAULT_ENTRYPOINTS.to_vec(),
+        };
+        order_names_first(&mut files, &entrypoints);
+    }
+    if matches.get_flag("manifests_first") {
+        order_names_first(&mut files, PACKAGE_MANIFESTS);
+    }
+    if matches.get_flag("tests_after_source") {
+        files = order_tests_after_source(files);
+    }
+    // Reordering can interleave directories, which would repeat their headers
+    if compact_headers {
+        files = group_by_dir(files);
+    }
+    files
+}
+
+/// How the selected files are read into blocks.
+struct ReadOptions<'a> {
+    block: BlockOptions<'a>,
+    root: &'a Path,
+    path_base: Option<&'a Path>,
+    focus_dir: Option<&'a Path>,
+    forced: &'a HashSet<PathBuf>,
+    forced_binary: &'a HashSet<PathBuf>,
+    manifests_first: bool,
+    max_processed_size: Option<u64>,
+    skip_empty_files: bool,
+    time_budget: Option<Duration>,
+    started: Instant,
+    /// Whether to record the file each emitted path was read from, for
+    /// `--validate`.
+    keep_sources: bool,
+    /// Whether to hash each file for a manifest.
+    hash_files: bool,
+}
+
+/// The blocks read from the selected files, keyed by their emitted paths.
+struct ReadBlocks {
+    blocks: Vec<(PathBuf, FileBlock)>,
+    sources: BTreeMap<String, PathBuf>,
+    manifest: BTreeMap<String, String>,
+    forced_blocks: HashSet<PathBuf>,
+    unbundled_by_time: usize,
+}
+
+/// Reads each selected file into a block, in bundle order, until the time
+/// budget runs out. Files that cannot be read are recorded in `failures`.
+fn read_blocks(
+    files: &[PathBuf],
+    options: &ReadOptions,
+    timings: &mut Timings,
+    failures: &mut Vec<(PathBuf, anyhow::Error)>,
+) -> ReadBlocks {
+    let raw_options = ProcessOptions {
+        raw: true,
+        ..Default::default()
+    };
+    let manifest_block_options = BlockOptions {
+        process: &raw_options,
+        ..options.block
+    };
+    let signature_options = ProcessOptions {
+        signatures_only: true,
+        ..options.block.process.clone()
+    };
+    let signature_block_options = BlockOptions {
+        process: &signature_options,
+        ..options.block
+    };
+
+    let mut read = ReadBlocks {
+        blocks: Vec::new(),
+        sources: BTreeMap::new(),
+        manifest: BTreeMap::new(),
+        forced_blocks: HashSet::new(),
+        unbundled_by_time: 0,
+    };
+    for (index, relative_file_path) in files.iter().enumerate() {
+        if options
+            .time_budget
+            .is_some_and(|budget| options.started.elapsed() > budget)
+        {
+            read.unbundled_by_time = files.len() - index;
+            break;
+        }
+        let file_path = options.root.join(relative_file_path);
+        let forced = options.forced.contains(relative_file_path);
+
+        // Files in the focus directory are read in full, those one step away
+        // as signatures, and the rest as just their path
+        let distance = match options.focus_dir {
+            Some(dir) if !forced => tree_distance(dir, relative_file_path),
+            _ => 0,
+        };
+        let block = if options.forced_binary.contains(relative_file_path) {
+            read_binary_block(&file_path)
+        } else if distance >= 2 {
+            path_only_block(&file_path)
+        } else if distance == 1 {
+            read_file_block(&file_path, &signature_block_options, timings)
+        } else if options.manifests_first && is_package_manifest(relative_file_path) {
+            read_file_block(&file_path, &manifest_block_options, timings)
+        } else {
+            read_file_block(&file_path, &options.block, timings)
+        };
+        let block = match block {
+            Ok(block) => block,
+            Err(e) => {
+                failures.push((relative_file_path.clone(), e));
+                continue;
+            }
+        };
+
+        if !forced {
+            if options
+                .max_processed_size
+                .is_some_and(|max| block.contents.len() as u64 > max)
+            {
+                continue;
+            }
+            if options.skip_empty_files && distance < 2 && block.contents.trim().is_empty() {
+                continue;
+            }
+        }
+
+        let emitted_path = emitted_path(options.root, options.path_base, relative_file_path);
+        if forced {
+            read.forced_blocks.insert(emitted_path.clone());
+        }
+        if options.keep_sources {
+            read.sources
+                .insert(emitted_path.display().to_string(), file_path.clone());
+        }
+        if options.hash_files {
+            match Oid::hash_file(ObjectType::Blob, &file_path) {
+                Ok(hash) => {
+                    read.manifest
+                        .insert(emitted_path.display().to_string(), hash.to_string());
+                }
+                Err(e) => {
+                    failures.push((relative_file_path.clone(), e.into()));
+                    continue;
+                }
+            }
+        }
+        read.blocks.push((emitted_path, block));
+    }
+    read
+}
+
+/// Notes about the run as a whole, written after the preamble.
+fn run_notes(
+    unbundled_by_time: usize,
+    capped_dirs: &BTreeMap<PathBuf, usize>,
+    sampled_out: &BTreeMap<String, usize>,
+    lockfile_summaries: Vec<String>,
+) -> Vec<String> {
+    let mut notes = Vec::new();
+    if unbundled_by_time > 0 {
+        notes.push(format!(
+            "the time budget ran out; {} remaining file(s) were not bundled",
+            unbundled_by_time
+        ));
+    }
+    for (dir, count) in capped_dirs {
+        let dir = if dir.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            dir.display().to_string()
+        };
+        notes.push(format!(
+            "{} more file(s) in {}/ were omitted by --max-files-per-dir",
+            count, dir
+        ));
+    }
+    for (extension, count) in sampled_out {
+        notes.push(format!(
+            "{} more .{} file(s) were omitted by --sample-ext",
+            count, extension
+        ));
+    }
+    notes.extend(lockfile_summaries);
+    notes
+}
+
+/// Keeps blocks while the bundle fits `max_tokens`, returning those kept and
+/// the summary line reporting the result.
+///
+/// Files are taken greedily in bundle order while they fit the budget.
+/// Forced files are always kept but still count toward it. Everything is
+/// estimated as rendered, with room kept for the summary line. Each block in
+/// a chunk may start a new compact directory header, so all are counted with
+/// one.
+fn apply_token_budget(
+    blocks: Vec<(PathBuf, FileBlock)>,
+    max_tokens: usize,
+    layout: &BundleLayout,
+    chunks: Option<usize>,
+    preamble_in_first: bool,
+    notes: &[String],
+    forced_blocks: &HashSet<PathBuf>,
+) -> Result<(Vec<(PathBuf, FileBlock)>, String)> {
+    let longest_summary = format!(
+        "Included {} file(s), skipped {} for the token budget, estimated {} tokens",
+        blocks.len(),
+        blocks.len(),
+        max_tokens
+    );
+    let mut estimated = estimate_overhead_tokens(
+        layout,
+        chunks.unwrap_or(1),
+        preamble_in_first,
+        notes,
+        &longest_summary,
+    )?;
+    let mut skipped = 0;
+    let mut current_dir = None;
+    let mut kept = Vec::with_capacity(blocks.len());
+    for (path, block) in blocks {
+        if chunks.is_some() {
+            current_dir = None;
+        }
+        let previous_dir = current_dir.clone();
+        let tokens = estimate_block_tokens(layout, &path, &block, &mut current_dir)?;
+        if estimated + tokens > max_tokens && !forced_blocks.contains(&path) {
+            skipped += 1;
+            current_dir = previous_dir;
+            continue;
+        }
+        estimated += tokens;
+        kept.push((path, block));
+    }
+    let summary = format!(
+        "Included {} file(s), skipped {} for the token budget, estimated {} tokens",
+        kept.len(),
+        skipped,
+        estimated
+    );
+    Ok((kept, summary))
+}
+
+/// Reports problems with the blocks about to be written: no files at all,
+/// shared file names, line endings, `--ascii-only` changes and marker lines.
+/// Fails where a flag asks for that, before any output is created.
+fn check_blocks(
+    blocks: &[(PathBuf, FileBlock)],
+    matches: &ArgMatches,
+    merging: bool,
+    style: &Style,
+) -> Result<()> {
+    if blocks.is_empty() && matches.get_flag("require_files") {
+        return Err(ExitError {
+            code: 2,
+            message: "No files would be included; check the include, exclude and other filters"
+                .to_string(),
+        }
+        .into());
+    }
+
+    // Shared file names are ambiguous once paths are shortened, as compact
+    // headers do, so they are always reported
+    let collisions = basename_collisions(blocks);
+    if !collisions.is_empty() {
+        report_basename_collisions(&collisions, style);
+        if matches.get_flag("fail_on_collision") {
+            anyhow::bail!("Included files share file names");
+        }
+    }
+
+    if matches.get_flag("eol_report") {
+        report_line_endings(blocks, style);
+    }
+    let non_ascii: Vec<usize> = blocks
+        .iter()
+        .map(|(_, block)| block.non_ascii)
+        .filter(|&count| count > 0)
+        .collect();
+    if !non_ascii.is_empty() {
+        let warning = format!(
+            "Warning: --ascii-only changed {} non-ASCII character(s) in {} file(s)",
+            non_ascii.iter().sum::<usize>(),
+            non_ascii.len()
+        );
+        eprintln!("{}", style.warning(&warning));
+    }
+
+    // A marker line inside a file or its note would end its block early when
+    // parsed, as would a `--- <name> ---` line in a file that is merged
+    let marker_files: Vec<&Path> = blocks
+        .iter()
+        .filter(|(_, block)| {
+            let notes = block.notes.iter().flat_map(|note| note.lines());
+            notes.chain(block.contents.lines()).any(|line| {
+                bundle::is_marker_line(line) || (merging && bundle::is_merged_file_line(line))
+            })
+        })
+        .map(|(path, _)| path.as_path())
+        .collect();
+    if !marker_files.is_empty() {
+        let warning = format!(
+            "Warning: {} file(s) contain a line that reads as a bundle marker:",
+            marker_files.len()
+        );
+        eprintln!("{}", style.warning(&warning));
+        for path in &marker_files {
+            eprintln!("  {}", path.display());
+        }
+        if matches.get_flag("strict_markers") {
+            return Err(ExitError {
+                code: 3,
+                message: "Refusing to write an ambiguous bundle".to_string(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// What is written around the blocks of each chunk.
+struct ChunkLayout<'a> {
+    layout: BundleLayout<'a>,
+    preamble_in_first: bool,
+    notes: &'a [String],
+    summary: Option<&'a str>,
+}
+
+/// Splits the blocks into one bundle per chunk path, and writes the split
+/// index if asked to. Notes about the whole run go at the start of the first
+/// chunk and the token summary at the end of the last.
+fn write_chunks(
+    blocks: &[(PathBuf, FileBlock)],
+    chunk_layout: &ChunkLayout,
+    chunk_paths: &[PathBuf],
+    split_index: Option<&Path>,
+) -> Result<()> {
+    let count = chunk_paths.len();
+    let chunked = balance_chunks(blocks, count, |(_, block)| block.size());
+    if let Some(index_path) = split_index {
+        write_split_index(index_path, &chunked, chunk_paths)?;
+    }
+    for (index, (chunk, path)) in chunked.iter().zip(chunk_paths).enumerate() {
+        let mut chunk_writer = BufWriter::new(
+            File::create(path)
+                .with_context(|| format!("Could not create chunk {}", path.display()))?,
+        );
+        let chunk_notes = if index == 0 { chunk_layout.notes } else { &[] };
+        let chunk_summary = chunk_layout.summary.filter(|_| index + 1 == count);
+        let preamble = chunk_preamble(
+            chunk_layout.layout.preamble,
+            index,
+            count,
+            chunk_layout.preamble_in_first,
+        );
+        let layout = BundleLayout {
+            preamble: &preamble,
+            ..chunk_layout.layout
+        };
+        write_bundle(
+            &mut chunk_writer,
+            &layout,
+            chunk,
+            chunk_notes,
+            chunk_summary,
+        )?;
+        chunk_writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_unicode_strips_zero_width_characters() {
+        let content = "\u{FEFF}zero\u{200B}width\u{200D} join\u{2060}er";
+        assert_eq!(
+            normalize_unicode(content, UnicodeForm::Nfc),
+            "zerowidth joiner"
+        );
+    }
+
+    #[test]
+    fn normalize_unicode_composes_to_the_chosen_form() {
+        let decomposed = "cafe\u{301} \u{FB01}";
+        assert_eq!(
+            normalize_unicode(decomposed, UnicodeForm::Nfc),
+            "caf\u{E9} \u{FB01}"
+        );
+        assert_eq!(
+            normalize_unicode(decomposed, UnicodeForm::Nfkc),
+            "caf\u{E9} fi"
+        );
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn tests_follow_their_source_file() {
+        let files = paths(&[
+            "src/a.rs",
+            "src/b.rs",
+            "tests/test_b.py",
+            "src/a_test.rs",
+            "b.py",
+            "src/b.spec.rs",
+            "orphan_test.rs",
+        ]);
+        assert_eq!(
+            order_tests_after_source(files),
+            paths(&[
+                "src/a.rs",
+                "src/a_test.rs",
+                "src/b.rs",
+                "src/b.spec.rs",
+                "b.py",
+                "tests/test_b.py",
+                "orphan_test.rs",
+            ])
+        );
+    }
+
+    #[test]
+    fn tests_paired_with_moved_tests_keep_their_place() {
+        let files = paths(&["foo_test_test.rs", "foo.rs", "foo_test.rs"]);
+        let ordered = order_tests_after_source(files.clone());
+        assert_eq!(ordered.len(), files.len());
+        assert_eq!(
+            ordered,
+            paths(&["foo_test_test.rs", "foo.rs", "foo_test.rs"])
+        );
+    }
+
+    #[test]
+    fn group_by_dir_keeps_first_appearance_order() {
+        let files = paths(&["src/a.rs", "b.rs", "src/a_test.rs", "c.rs", "src/x/d.rs"]);
+        assert_eq!(
+            group_by_dir(files),
+            paths(&["src/a.rs", "src/a_test.rs", "b.rs", "c.rs", "src/x/d.rs"])
+        );
+    }
+
+    #[test]
+    fn strip_python_docstrings_removes_only_leading_strings() {
+        let source = r#"#!/usr/bin/env python
+"""Module docstring
+spanning lines."""
+import os
+
+
+class Greeter:
+    'Class docstring.'
+
+    def greet(
+        self, name,
+    ):
+        """Function docstring."""
+        message = """Not a docstring."""
+        return message
+
+
+async def main(): ...
+"#;
+        assert_eq!(
+            strip_python_docstrings(source),
+            r#"#!/usr/bin/env python
+import os
+
+
+class Greeter:
+
+    def greet(
+        self, name,
+    ):
+        message = """Not a docstring."""
+        return message
+
+
+async def main(): ...
+"#
+        );
+    }
+
+    #[test]
+    fn parse_sample_ext_accepts_an_optional_dot() {
+        assert_eq!(parse_sample_ext(".json=2"), Ok(("json".to_string(), 2)));
+        assert_eq!(parse_sample_ext("rs=0"), Ok(("rs".to_string(), 0)));
+        assert!(parse_sample_ext("json").is_err());
+        assert!(parse_sample_ext("json=many").is_err());
+    }
+
+    #[test]
+    fn cap_per_group_keeps_the_first_paths_of_each_group() {
+        let mut files = paths(&["d/b.json", "c.rs", "a.json", "d/a.json"]);
+        let dropped = cap_per_group(&mut files, |file| {
+            let extension = file.extension()?.to_str()?;
+            (extension == "json").then(|| (extension.to_string(), 2))
+        });
+        assert_eq!(files, paths(&["c.rs", "a.json", "d/a.json"]));
+        assert_eq!(dropped, BTreeMap::from([("json".to_string(), 1)]));
+    }
+
+    #[test]
+    fn parse_duration_accepts_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+
+    #[test]
+    fn parse_size_rule_accepts_suffixes() {
+        let rule = parse_size_rule("*.json:2k").unwrap();
+        assert_eq!(rule.max_bytes, 2048);
+        assert!(rule.matcher.is_match("data/big.json"));
+        assert_eq!(parse_size_rule("*.csv:3M").unwrap().max_bytes, 3 << 20);
+        assert_eq!(parse_size_rule("c:/*.txt:10").unwrap().max_bytes, 10);
+    }
+
+    #[test]
+    fn parse_size_rule_rejects_bad_sizes() {
+        assert!(parse_size_rule("*.json").is_err());
+        assert!(parse_size_rule("*.json:big").is_err());
+        assert!(parse_size_rule("*.json:k").is_err());
+        assert!(parse_size_rule("*.json:18446744073709551615k").is_err());
+    }
+
+    #[test]
+    fn line_endings_are_classified() {
+        assert_eq!(line_endings("a\nb\n"), LineEndings::Lf);
+        assert_eq!(line_endings("no newline"), LineEndings::Lf);
+        assert_eq!(line_endings("a\r\nb\r\n"), LineEndings::Crlf);
+        assert_eq!(line_endings("a\r\nb\n"), LineEndings::Mixed);
+    }
+
+    #[test]
+    fn balance_chunks_evens_out_sizes_and_keeps_order() {
+        let sizes = [5, 1, 4, 2, 3, 5];
+        let chunks = balance_chunks(&sizes, 2, |&size| size);
+        assert_eq!(chunks, [vec![&5, &1, &4], vec![&2, &3, &5]]);
+
+        let chunks = balance_chunks(&sizes[..1], 3, |&size| size);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.iter().map(Vec::len).sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn labeled_path_goes_before_the_extension() {
+        assert_eq!(
+            labeled_path(Path::new("out/bundle.txt"), 2),
+            Path::new("out/bundle.2.txt")
+        );
+        assert_eq!(labeled_path(Path::new("bundle"), 1), Path::new("bundle.1"));
+    }
+
+    #[test]
+    fn order_names_first_follows_the_name_list() {
+        let mut files = paths(&["a.rs", "src/lib.rs", "src/main.rs", "b/main.rs", "c.rs"]);
+        order_names_first(&mut files, &["main.rs", "lib.rs"]);
+        assert_eq!(
+            files,
+            paths(&["src/main.rs", "b/main.rs", "src/lib.rs", "a.rs", "c.rs"])
+        );
+    }
+
+    #[test]
+    fn parse_fraction_accepts_only_zero_to_one() {
+        assert_eq!(parse_fraction("0"), Ok(0.0));
+        assert_eq!(parse_fraction("0.25"), Ok(0.25));
+        assert_eq!(parse_fraction("1"), Ok(1.0));
+        assert!(parse_fraction("1.5").is_err());
+        assert!(parse_fraction("-0.1").is_err());
+        assert!(parse_fraction("NaN").is_err());
+    }
+
+    #[test]
+    fn keep_head_and_tail_marks_the_omitted_lines() {
+        let content = "1\n2\n3\n4\n5\n6\n";
+        assert_eq!(
+            keep_head_and_tail(content, 2).unwrap(),
+            "1\n2\n[... 2 lines omitted ...]\n5\n6\n"
+        );
+        assert_eq!(
+            keep_head_and_tail(content, 0).unwrap(),
+            "[... 6 lines omitted ...]\n"
+        );
+        // Nothing would be left out
+        assert!(keep_head_and_tail(content, 3).is_none());
+        assert!(keep_head_and_tail(content, 4).is_none());
+    }
+
+    #[test]
+    fn keep_head_and_tail_keeps_everything_for_huge_counts() {
+        assert!(keep_head_and_tail("1\n2\n", usize::MAX).is_none());
+        assert!(keep_head_and_tail("1\n2\n", usize::MAX / 2 + 1).is_none());
+    }
+
+    #[test]
+    fn to_ascii_replaces_or_strips_each_character() {
+        assert_eq!(
+            to_ascii(
+                "na\u{EF}ve \u{2014} caf\u{E9}".to_string(),
+                AsciiMode::Replace
+            ),
+            ("na?ve ? caf?".to_string(), 3)
+        );
+        assert_eq!(
+            to_ascii(
+                "na\u{EF}ve \u{2014} caf\u{E9}".to_string(),
+                AsciiMode::Strip
+            ),
+            ("nave  caf".to_string(), 3)
+        );
+        assert_eq!(
+            to_ascii("plain".to_string(), AsciiMode::Strip),
+            ("plain".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn hashed_outputs_sit_next_to_the_output_path() {
+        let output = Path::new("out/bundle.txt");
+        assert!(is_hashed_output(
+            output,
+            Path::new("out/bundle.0123456789ab.txt")
+        ));
+        assert!(!is_hashed_output(
+            output,
+            Path::new("bundle.0123456789ab.txt")
+        ));
+        assert!(!is_hashed_output(
+            output,
+            Path::new("out/bundle.0123456789.txt")
+        ));
+        assert!(!is_hashed_output(
+            output,
+            Path::new("out/bundle.0123456789xy.txt")
+        ));
+        assert!(!is_hashed_output(
+            output,
+            Path::new("out/other.0123456789ab.txt")
+        ));
+        assert!(is_hashed_output(
+            Path::new("bundle"),
+            Path::new("bundle.0123456789ab")
+        ));
+    }
+
+    fn block(contents: &str) -> FileBlock {
+        FileBlock {
+            notes: Vec::new(),
+            symbols: Vec::new(),
+            line_endings: None,
+            non_ascii: 0,
+            contents: contents.to_string(),
+            merged: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn merge_small_blocks_combines_small_files_per_directory() {
+        let blocks = vec![
+            (PathBuf::from("src/a.rs"), block("a")),
+            (PathBuf::from("src/big.rs"), block("a large file")),
+            (PathBuf::from("lone.txt"), block("l")),
+            (PathBuf::from("src/b.rs"), block("b")),
+        ];
+        let merged = merge_small_blocks(blocks, 5);
+        let paths: Vec<Vec<&Path>> = merged
+            .iter()
+            .map(|(path, block)| block_paths(path, block))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                vec![Path::new("src/a.rs"), Path::new("src/b.rs")],
+                vec![Path::new("src/big.rs")],
+                vec![Path::new("lone.txt")],
+            ]
+        );
+        assert_eq!(merged[0].0, Path::new("src/*"));
+        assert_eq!(merged[0].1.size(), 2);
+    }
+
+    #[test]
+    fn chunk_preamble_continues_after_the_first_chunk() {
+        assert_eq!(chunk_preamble("Intro\n", 0, 3, true), "Intro\n");
+        assert_eq!(
+            chunk_preamble("Intro\n", 2, 3, true),
+            "(continued, chunk 3/3)\n"
+        );
+        assert_eq!(chunk_preamble("Intro\n", 2, 3, false), "Intro\n");
+    }
+
+    #[test]
+    fn tree_distance_counts_steps_between_directories() {
+        assert_eq!(tree_distance(Path::new("src"), Path::new("src/main.rs")), 0);
+        assert_eq!(tree_distance(Path::new("src"), Path::new("lib.rs")), 1);
+        assert_eq!(
+            tree_distance(Path::new("src"), Path::new("src/cli/args.rs")),
+            1
+        );
+        assert_eq!(
+            tree_distance(Path::new("src/core"), Path::new("src/cli/args.rs")),
+            2
+        );
+        assert_eq!(tree_distance(Path::new(""), Path::new("docs/guide.md")), 1);
+    }
 }
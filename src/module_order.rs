@@ -0,0 +1,266 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Reorders the Rust files among `files` so that each module comes after
+/// the modules it declares with `mod` or refers to through `crate::`,
+/// `super::` or `self::` paths. Other files keep their positions, and files
+/// caught in a cycle fall back to their original order.
+///
+/// Module paths are worked out from the file layout below the nearest
+/// directory holding a `lib.rs` or `main.rs`, and references are found by
+/// scanning the source rather than parsing it, so this is best-effort.
+pub fn order(root: &Path, files: &[PathBuf]) -> Vec<PathBuf> {
+    let rust_slots: Vec<usize> = (0..files.len())
+        .filter(|&index| files[index].extension().is_some_and(|ext| ext == "rs"))
+        .collect();
+    let rust_files: Vec<&PathBuf> = rust_slots.iter().map(|&index| &files[index]).collect();
+
+    let modules: Vec<Option<(PathBuf, Vec<String>)>> = rust_files
+        .iter()
+        .map(|file| module_path(root, file))
+        .collect();
+    let by_module: HashMap<&(PathBuf, Vec<String>), usize> = modules
+        .iter()
+        .enumerate()
+        .filter_map(|(index, module)| Some((module.as_ref()?, index)))
+        .collect();
+
+    let mut dependencies: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); rust_files.len()];
+    for (index, module) in modules.iter().enumerate() {
+        let Some((crate_dir, path)) = module else {
+            continue;
+        };
+        let Ok(source) = fs::read_to_string(root.join(rust_files[index])) else {
+            continue;
+        };
+        for reference in references(&source, path) {
+            // The longest prefix naming a known module is the dependency
+            let found = (1..=reference.len())
+                .rev()
+                .find_map(|len| by_module.get(&(crate_dir.clone(), reference[..len].to_vec())));
+            if let Some(&dependency) = found.filter(|&&dependency| dependency != index) {
+                dependencies[index].insert(dependency);
+            }
+        }
+    }
+
+    let sorted = topological_order(&dependencies);
+    let mut ordered = files.to_vec();
+    for (&slot, index) in rust_slots.iter().zip(sorted) {
+        ordered[slot] = rust_files[index].clone();
+    }
+    ordered
+}
+
+/// Returns the directory of the crate a file belongs to and its module path
+/// within that crate, such as `src` and `["net", "client"]` for
+/// `src/net/client.rs` or `src/net/client/mod.rs`.
+fn module_path(root: &Path, file: &Path) -> Option<(PathBuf, Vec<String>)> {
+    let crate_dir = file.ancestors().skip(1).find(|dir| {
+        root.join(dir).join("lib.rs").is_file() || root.join(dir).join("main.rs").is_file()
+    })?;
+    let mut path: Vec<String> = file
+        .strip_prefix(crate_dir)
+        .ok()?
+        .with_extension("")
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if path.len() == 1 && (path[0] == "lib" || path[0] == "main") {
+        path.clear();
+    } else if path.last().is_some_and(|last| last == "mod") {
+        path.pop();
+    }
+    Some((crate_dir.to_path_buf(), path))
+}
+
+/// Lists the absolute module paths that `source`, the module at `module`,
+/// declares or refers to.
+fn references(source: &str, module: &[String]) -> Vec<Vec<String>> {
+    let mut references = Vec::new();
+    for line in source.lines() {
+        let line = line.trim_start();
+        if line.starts_with("//") {
+            continue;
+        }
+        if let Some(name) = declared_module(line) {
+            let mut child = module.to_vec();
+            child.push(name.to_string());
+            references.push(child);
+        }
+
+        for (start, _) in line.match_indices("::") {
+            let Some(mut base) = path_base(&line[..start], module) else {
+                continue;
+            };
+            let mut rest = &line[start + 2..];
+            while let Some(after) = rest.strip_prefix("super::") {
+                base.pop();
+                rest = after;
+            }
+            for segments in path_segments(rest) {
+                let mut reference = base.clone();
+                reference.extend(segments);
+                references.push(reference);
+            }
+        }
+    }
+    references
+}
+
+/// Matches an out-of-line module declaration such as `pub mod name;`.
+fn declared_module(line: &str) -> Option<&str> {
+    let line = line
+        .strip_prefix("pub(crate) ")
+        .or_else(|| line.strip_prefix("pub "))
+        .unwrap_or(line);
+    let name = line.strip_prefix("mod ")?.trim().strip_suffix(';')?;
+    name.chars()
+        .all(|c| c.is_alphanumeric() || c == '_')
+        .then_some(name)
+}
+
+/// Resolves the keyword just before a `::`, if it is `crate`, `super` or
+/// `self`, to the module path it stands for.
+fn path_base(before: &str, module: &[String]) -> Option<Vec<String>> {
+    let start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |index| index + 1);
+    // Repeated keywords as in super::super are resolved from the first
+    if before[..start].ends_with("::") {
+        return None;
+    }
+    let mut base = module.to_vec();
+    match &before[start..] {
+        "crate" => base.clear(),
+        "self" => {}
+        "super" => {
+            base.pop();
+        }
+        _ => return None,
+    }
+    Some(base)
+}
+
+/// Splits what follows `crate::` and the like into module segments, taking
+/// each item of a `{a, b::c}` group as a separate path.
+fn path_segments(rest: &str) -> Vec<Vec<String>> {
+    let segments = |path: &str| -> Vec<String> {
+        path.split("::")
+            .map(str::trim)
+            .take_while(|segment| {
+                !segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_')
+            })
+            .map(String::from)
+            .collect()
+    };
+
+    let end = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':' || c == '{'))
+        .unwrap_or(rest.len());
+    let (prefix, group) = match rest.find('{') {
+        Some(open) if open <= end => (&rest[..open], rest[open + 1..].split('}').next()),
+        _ => (&rest[..end], None),
+    };
+    let prefix = segments(prefix.trim_end_matches("::"));
+    match group {
+        Some(group) => group
+            .split(',')
+            .map(|item| {
+                let mut path = prefix.clone();
+                path.extend(segments(item));
+                path
+            })
+            .collect(),
+        None => vec![prefix],
+    }
+}
+
+/// Orders node indices so that dependencies come first, preferring lower
+/// indices. When a cycle leaves no node ready, the lowest remaining index is
+/// taken next.
+fn topological_order(dependencies: &[BTreeSet<usize>]) -> Vec<usize> {
+    let mut dependents = vec![Vec::new(); dependencies.len()];
+    for (index, node_dependencies) in dependencies.iter().enumerate() {
+        for &dependency in node_dependencies {
+            dependents[dependency].push(index);
+        }
+    }
+    let mut waiting_on: Vec<usize> = dependencies.iter().map(BTreeSet::len).collect();
+    let mut remaining: BTreeSet<usize> = (0..dependencies.len()).collect();
+    let mut ready: BTreeSet<usize> = remaining
+        .iter()
+        .copied()
+        .filter(|&index| waiting_on[index] == 0)
+        .collect();
+
+    let mut ordered = Vec::with_capacity(dependencies.len());
+    while let Some(&lowest) = remaining.first() {
+        let next = ready.pop_first().unwrap_or(lowest);
+        if !remaining.remove(&next) {
+            continue;
+        }
+        ordered.push(next);
+        for &dependent in &dependents[next] {
+            waiting_on[dependent] -= 1;
+            if waiting_on[dependent] == 0 && remaining.contains(&dependent) {
+                ready.insert(dependent);
+            }
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(path: &[&str]) -> Vec<String> {
+        path.iter().map(|segment| segment.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_declared_and_referenced_modules() {
+        let source = "\
+pub mod client;
+mod server { }
+// use crate::ignored;
+use crate::config::{Config, load::from_file};
+use super::super::util;
+let x = self::helpers::run();
+";
+        assert_eq!(
+            references(source, &module(&["net", "http"])),
+            [
+                module(&["net", "http", "client"]),
+                module(&["config", "Config"]),
+                module(&["config", "load", "from_file"]),
+                module(&["util"]),
+                module(&["net", "http", "helpers", "run"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_paths_not_rooted_in_the_crate() {
+        assert!(references("use std::fmt;\nuse other::crate_like::x;\n", &[]).is_empty());
+    }
+
+    #[test]
+    fn orders_dependencies_first() {
+        // 0 uses 2, 2 uses 1
+        let dependencies = [BTreeSet::from([2]), BTreeSet::new(), BTreeSet::from([1])];
+        assert_eq!(topological_order(&dependencies), [1, 2, 0]);
+    }
+
+    #[test]
+    fn breaks_cycles_at_the_lowest_index() {
+        let dependencies = [
+            BTreeSet::from([1]),
+            BTreeSet::from([0]),
+            BTreeSet::from([0]),
+        ];
+        assert_eq!(topological_order(&dependencies), [0, 1, 2]);
+    }
+}
@@ -0,0 +1,95 @@
+use serde_json::Value;
+
+/// Renders a Jupyter notebook as its cell sources, each introduced by a
+/// `# %%` marker as in the percent script format. Outputs, which are often
+/// large base64 blobs, are only included as plain text when requested.
+/// Returns `None` if the contents are not a notebook.
+pub fn render(contents: &str, include_outputs: bool) -> Option<String> {
+    let notebook: Value = serde_json::from_str(contents).ok()?;
+    let cells = notebook.get("cells")?.as_array()?;
+
+    let mut rendered = String::new();
+    for cell in cells {
+        match cell.get("cell_type").and_then(Value::as_str) {
+            Some("markdown") => rendered.push_str("# %% [markdown]\n"),
+            Some("code") => rendered.push_str("# %%\n"),
+            _ => continue,
+        }
+        push_lines(&mut rendered, &text(cell.get("source")));
+
+        if !include_outputs {
+            continue;
+        }
+        let outputs = cell.get("outputs").and_then(Value::as_array);
+        for output in outputs.into_iter().flatten() {
+            let output_text = match output.get("text") {
+                Some(stream) => text(Some(stream)),
+                None => text(output.get("data").and_then(|data| data.get("text/plain"))),
+            };
+            if !output_text.is_empty() {
+                rendered.push_str("# Output:\n");
+                push_lines(&mut rendered, &output_text);
+            }
+        }
+    }
+    Some(rendered)
+}
+
+/// Notebook text fields are either a string or a list of lines.
+fn text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+fn push_lines(rendered: &mut String, text: &str) {
+    rendered.push_str(text);
+    if !text.ends_with('\n') {
+        rendered.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTEBOOK: &str = r##"{
+        "cells": [
+            {"cell_type": "markdown", "source": ["# Title\n", "Intro"]},
+            {"cell_type": "raw", "source": "skipped"},
+            {
+                "cell_type": "code",
+                "source": "print(1)",
+                "outputs": [
+                    {"output_type": "stream", "text": ["1\n"]},
+                    {"output_type": "display_data", "data": {"image/png": "iVBORw0KGgo="}},
+                    {"output_type": "execute_result", "data": {"text/plain": "2"}}
+                ]
+            }
+        ]
+    }"##;
+
+    #[test]
+    fn renders_cell_sources_in_percent_format() {
+        assert_eq!(
+            render(NOTEBOOK, false).unwrap(),
+            "# %% [markdown]\n# Title\nIntro\n# %%\nprint(1)\n"
+        );
+    }
+
+    #[test]
+    fn renders_text_outputs_when_asked() {
+        assert_eq!(
+            render(NOTEBOOK, true).unwrap(),
+            "# %% [markdown]\n# Title\nIntro\n# %%\nprint(1)\n# Output:\n1\n# Output:\n2\n"
+        );
+    }
+
+    #[test]
+    fn rejects_other_json() {
+        assert!(render("{\"name\": \"package\"}", false).is_none());
+        assert!(render("not json", false).is_none());
+    }
+}
@@ -0,0 +1,166 @@
+/// Lists the top-level symbols of a source file as `<kind> <name>` entries,
+/// for Rust, Python, Go, JavaScript and TypeScript. Only unindented lines are
+/// considered, so nested items such as methods are left out.
+pub fn outline(extension: &str, content: &str) -> Vec<String> {
+    let symbol: fn(&str) -> Option<(&'static str, &str)> = match extension {
+        "rs" => rust_symbol,
+        "py" => python_symbol,
+        "go" => go_symbol,
+        "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" => javascript_symbol,
+        _ => return Vec::new(),
+    };
+
+    content
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(symbol)
+        .map(|(kind, name)| format!("{} {}", kind, name))
+        .collect()
+}
+
+fn identifier(s: &str) -> Option<&str> {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .unwrap_or(s.len());
+    (end > 0).then(|| &s[..end])
+}
+
+/// Matches `<keyword> <identifier>` at the start of `line`.
+fn keyword_symbol<'a>(line: &'a str, keywords: &[&'static str]) -> Option<(&'static str, &'a str)> {
+    keywords.iter().find_map(|&keyword| {
+        let rest = line.strip_prefix(keyword)?.strip_prefix(' ')?;
+        Some((keyword, identifier(rest.trim_start())?))
+    })
+}
+
+/// Strips any sequence of modifier words, such as visibility, from the start
+/// of `line`.
+fn strip_prefixes<'a>(mut line: &'a str, prefixes: &[&str]) -> &'a str {
+    while let Some(rest) = prefixes.iter().find_map(|prefix| {
+        line.strip_prefix(prefix)
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+    }) {
+        line = rest.trim_start();
+    }
+    line
+}
+
+fn rust_symbol(line: &str) -> Option<(&'static str, &str)> {
+    let modifiers = [
+        "pub(crate)",
+        "pub(super)",
+        "pub",
+        "async",
+        "unsafe",
+        "extern \"C\"",
+    ];
+    let line = strip_prefixes(line, &modifiers);
+    if let Some(rest) = line.strip_prefix("macro_rules!") {
+        return Some(("macro", identifier(rest.trim_start())?));
+    }
+    if let Some(rest) = line.strip_prefix("const ") {
+        let rest = strip_prefixes(rest.trim_start(), &modifiers);
+        if rest.starts_with("fn ") {
+            return keyword_symbol(rest, &["fn"]);
+        }
+        return Some(("const", identifier(rest)?));
+    }
+    keyword_symbol(
+        line,
+        &[
+            "fn", "struct", "enum", "trait", "type", "mod", "union", "static",
+        ],
+    )
+}
+
+fn python_symbol(line: &str) -> Option<(&'static str, &str)> {
+    keyword_symbol(strip_prefixes(line, &["async"]), &["def", "class"])
+}
+
+fn go_symbol(line: &str) -> Option<(&'static str, &str)> {
+    if let Some(rest) = line.strip_prefix("func (") {
+        // Method: skip the receiver
+        let (_, rest) = rest.split_once(')')?;
+        return Some(("func", identifier(rest.trim_start())?));
+    }
+    keyword_symbol(line, &["func", "type"])
+}
+
+fn javascript_symbol(line: &str) -> Option<(&'static str, &str)> {
+    let line = strip_prefixes(line, &["export", "default", "declare", "abstract", "async"]);
+    if let Some(rest) = line.strip_prefix("function*") {
+        return Some(("function", identifier(rest.trim_start())?));
+    }
+    keyword_symbol(line, &["function", "class", "interface", "type", "enum"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outlines_top_level_rust_items() {
+        let source = "\
+use std::fmt;
+
+pub struct Parser {
+    depth: usize,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser { depth: 0 }
+    }
+}
+
+pub(crate) enum Token {}
+pub const fn limit() -> usize { 8 }
+const MAX: usize = 8;
+pub unsafe trait Sealed {}
+macro_rules! bail {
+    () => {};
+}
+async fn run() {}
+mod tests;
+";
+        assert_eq!(
+            outline("rs", source),
+            [
+                "struct Parser",
+                "enum Token",
+                "fn limit",
+                "const MAX",
+                "trait Sealed",
+                "macro bail",
+                "fn run",
+                "mod tests",
+            ]
+        );
+    }
+
+    #[test]
+    fn outlines_other_languages() {
+        assert_eq!(
+            outline(
+                "py",
+                "class A:\n    def m(self): ...\nasync def main(): ...\n"
+            ),
+            ["class A", "def main"]
+        );
+        assert_eq!(
+            outline(
+                "go",
+                "type T struct{}\nfunc (t *T) Run() {}\nfunc main() {}\n"
+            ),
+            ["type T", "func Run", "func main"]
+        );
+        assert_eq!(
+            outline(
+                "ts",
+                "export default class App {}\nexport function* ids() {}\n"
+            ),
+            ["class App", "function ids"]
+        );
+        assert!(outline("txt", "fn not_code() {}\n").is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+#[derive(Clone, Copy)]
+enum Language {
+    Rust,
+    TypeScript,
+    Go,
+}
+
+/// Replaces every function body in Rust, TypeScript or Go source with
+/// `{ ... }`, keeping signatures, type definitions and the items inside
+/// `impl`, `trait` and `class` blocks. Returns `None` for other languages.
+///
+/// This is a lightweight scanner rather than a parser: it skips comments and
+/// string literals while matching braces, and decides whether a brace opens a
+/// function body from the code since the previous `;`, `{` or `}`.
+pub fn elide_bodies(extension: &str, source: &str) -> Option<String> {
+    let language = match extension {
+        "rs" => Language::Rust,
+        "ts" | "tsx" | "mts" | "cts" => Language::TypeScript,
+        "go" => Language::Go,
+        _ => return None,
+    };
+
+    let mut elided = String::with_capacity(source.len());
+    let mut header = String::new();
+    let mut bracket_depth = 0i32;
+    let mut i = 0;
+    while i < source.len() {
+        if let Some(end) = skip_literal(source, i, language) {
+            elided.push_str(&source[i..end]);
+            if !is_comment(source, i) {
+                header.push_str("\"\"");
+            }
+            i = end;
+            continue;
+        }
+
+        let c = source[i..].chars().next().unwrap_or_default();
+        match c {
+            '(' | '[' => {
+                bracket_depth += 1;
+                header.push(c);
+            }
+            ')' | ']' => {
+                bracket_depth -= 1;
+                header.push(c);
+            }
+            '{' if bracket_depth <= 0 && opens_function_body(&header, language) => {
+                elided.push_str("{ ... }");
+                i = matching_brace(source, i, language);
+                header.clear();
+                continue;
+            }
+            '{' | '}' => header.clear(),
+            ';' if bracket_depth <= 0 => header.clear(),
+            _ => header.push(c),
+        }
+        elided.push(c);
+        i += c.len_utf8();
+    }
+    Some(elided)
+}
+
+fn is_comment(source: &str, i: usize) -> bool {
+    source[i..].starts_with("//") || source[i..].starts_with("/*")
+}
+
+fn has_word(header: &str, word: &str) -> bool {
+    header
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .any(|token| token == word)
+}
+
+fn opens_function_body(header: &str, language: Language) -> bool {
+    match language {
+        Language::Rust => has_word(header, "fn"),
+        Language::Go => has_word(header, "func"),
+        Language::TypeScript => {
+            let first_word = header
+                .split_whitespace()
+                .find(|word| !matches!(*word, "export" | "default" | "declare" | "abstract"));
+            let is_type_block = matches!(
+                first_word,
+                Some("class" | "interface" | "enum" | "namespace" | "module" | "type")
+            );
+            has_word(header, "function")
+                || header.trim_end().ends_with("=>")
+                || (header.contains(')') && !is_type_block)
+        }
+    }
+}
+
+/// Returns the index just past the `}` matching the `{` at `open`.
+fn matching_brace(source: &str, open: usize, language: Language) -> usize {
+    let mut depth = 0;
+    let mut i = open;
+    while i < source.len() {
+        if let Some(end) = skip_literal(source, i, language) {
+            i = end;
+            continue;
+        }
+        let c = source[i..].chars().next().unwrap_or_default();
+        i += c.len_utf8();
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    source.len()
+}
+
+/// If a comment or string literal starts at `i`, returns the index just past
+/// its end.
+fn skip_literal(source: &str, i: usize, language: Language) -> Option<usize> {
+    let rest = &source[i..];
+    if rest.starts_with("//") {
+        return Some(rest.find('\n').map_or(source.len(), |end| i + end));
+    }
+    if let Some(comment) = rest.strip_prefix("/*") {
+        return Some(
+            comment
+                .find("*/")
+                .map_or(source.len(), |end| i + 2 + end + 2),
+        );
+    }
+
+    let bytes = rest.as_bytes();
+    match (language, bytes[0]) {
+        (_, b'"') => Some(skip_quoted(source, i, b'"', true)),
+        (Language::TypeScript, b'\'') => Some(skip_quoted(source, i, b'\'', true)),
+        (Language::TypeScript, b'`') => Some(skip_quoted(source, i, b'`', true)),
+        (Language::Go, b'`') => Some(skip_quoted(source, i, b'`', false)),
+        (Language::Go, b'\'') => Some(skip_quoted(source, i, b'\'', true)),
+        (Language::Rust, b'\'') => {
+            // A character literal, unlike a lifetime, closes after one char
+            let mut chars = rest[1..].chars();
+            match (chars.next(), chars.next()) {
+                (Some('\\'), _) => Some(skip_quoted(source, i, b'\'', true)),
+                (Some(c), Some('\'')) => Some(i + 1 + c.len_utf8() + 1),
+                _ => None,
+            }
+        }
+        (Language::Rust, b'r') if !follows_identifier(source, i) => skip_raw_string(source, i),
+        _ => None,
+    }
+}
+
+fn follows_identifier(source: &str, i: usize) -> bool {
+    source[..i]
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn skip_quoted(source: &str, open: usize, quote: u8, escapes: bool) -> usize {
+    let bytes = source.as_bytes();
+    let mut i = open + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if escapes => i += 2,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    source.len()
+}
+
+/// Skips a Rust raw string such as `r"..."` or `r#"..."#`.
+fn skip_raw_string(source: &str, open: usize) -> Option<usize> {
+    let rest = &source[open + 1..];
+    let hashes = rest.len() - rest.trim_start_matches('#').len();
+    if !rest[hashes..].starts_with('"') {
+        return None;
+    }
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    let body_start = open + 1 + hashes + 1;
+    Some(
+        source[body_start..]
+            .find(&terminator)
+            .map_or(source.len(), |end| body_start + end + terminator.len()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elides_rust_function_bodies() {
+        let source = r##"struct Point { x: i32 }
+
+impl Point {
+    pub fn new(x: i32) -> Self {
+        let brace = '{';
+        let text = r#"}"#;
+        Point { x } // }
+    }
+}
+
+fn run<'a>(f: impl Fn(&'a str)) where 'a: 'static {
+    f("}")
+}
+"##;
+        assert_eq!(
+            elide_bodies("rs", source).unwrap(),
+            "struct Point { x: i32 }
+
+impl Point {
+    pub fn new(x: i32) -> Self { ... }
+}
+
+fn run<'a>(f: impl Fn(&'a str)) where 'a: 'static { ... }
+"
+        );
+    }
+
+    #[test]
+    fn elides_typescript_and_go_function_bodies() {
+        let source = "interface Shape { area(): number }
+export class Square {
+    area(): number { return `${1}}`.length; }
+}
+const double = (x: number) => { return x * 2; };
+";
+        assert_eq!(
+            elide_bodies("ts", source).unwrap(),
+            "interface Shape { area(): number }
+export class Square {
+    area(): number { ... }
+}
+const double = (x: number) => { ... };
+"
+        );
+
+        let source = "type T struct { n int }\nfunc (t T) N() int {\n\treturn t.n\n}\n";
+        assert_eq!(
+            elide_bodies("go", source).unwrap(),
+            "type T struct { n int }\nfunc (t T) N() int { ... }\n"
+        );
+    }
+
+    #[test]
+    fn leaves_other_languages_alone() {
+        assert!(elide_bodies("py", "def f():\n    pass\n").is_none());
+    }
+}
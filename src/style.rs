@@ -0,0 +1,52 @@
+use std::io::IsTerminal;
+
+/// ANSI coloring for the diagnostics written to stderr.
+#[derive(Clone, Copy)]
+pub struct Style {
+    color: bool,
+}
+
+impl Style {
+    /// `when` is `always`, `never` or `auto`, which colors only when stderr
+    /// is a terminal.
+    pub fn new(when: &str) -> Self {
+        let color = match when {
+            "always" => true,
+            "never" => false,
+            _ => std::io::stderr().is_terminal(),
+        };
+        Style { color }
+    }
+
+    pub fn heading(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn warning(&self, text: &str) -> String {
+        self.paint("33", text)
+    }
+
+    pub fn error(&self, text: &str) -> String {
+        self.paint("31", text)
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paints_only_when_color_is_on() {
+        assert_eq!(Style::new("always").warning("w"), "\x1b[33mw\x1b[0m");
+        assert_eq!(Style::new("never").warning("w"), "w");
+        assert_eq!(Style::new("never").error("e"), "e");
+    }
+}
@@ -0,0 +1,1626 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::TestRepo;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use git2::{Repository, Signature};
+
+#[test]
+fn compact_headers_name_each_directory_once() {
+    let repo = TestRepo::new();
+    repo.write("src/main.rs", "fn main() {}\n")
+        .write("src/a/b.txt", "b\n")
+        .write("src/a/c.txt", "c\n");
+
+    let bundle = repo.bundle(&["--compact-headers"]);
+
+    assert_eq!(bundle.matches("@@@@src/a/@@@@").count(), 1);
+    assert!(bundle.contains("@@@@src/@@@@\n@@@@main.rs@@@@\n"));
+    assert!(bundle.contains("@@@@src/a/@@@@\n@@@@b.txt@@@@\nb\n\n@@@@c.txt@@@@\nc\n"));
+    assert!(!bundle.contains("@@@@src/a/b.txt@@@@"));
+}
+
+#[test]
+fn sidecar_notes_precede_their_file_and_are_not_bundled_alone() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", "pub fn f() {}\n")
+        .write("lib.rs.gprepo.md", "Kept for the old API.\n")
+        .write("orphan.gprepo.md", "No file of this name.\n");
+
+    let bundle = repo.bundle(&[]);
+
+    assert!(bundle.contains("@@@@lib.rs@@@@\nNote: Kept for the old API.\npub fn f() {}\n"));
+    assert!(!bundle.contains("@@@@lib.rs.gprepo.md@@@@"));
+    assert!(bundle.contains("@@@@orphan.gprepo.md@@@@\nNo file of this name.\n"));
+}
+
+#[test]
+fn last_author_keeps_files_whose_latest_commit_is_theirs() {
+    let repo = TestRepo::new();
+    repo.write("ada.txt", "one\n")
+        .write("shared.txt", "one\n")
+        .write("grace.txt", "one\n")
+        .commit("Ada", "ada@example.com", "Add files");
+    repo.write("shared.txt", "two\n")
+        .write("grace.txt", "two\n")
+        .commit("Grace", "grace@example.com", "Edit files");
+
+    let bundle = repo.bundle(&["--last-author", "Ada"]);
+    assert!(bundle.contains("@@@@ada.txt@@@@"));
+    assert!(!bundle.contains("@@@@shared.txt@@@@"));
+    assert!(!bundle.contains("@@@@grace.txt@@@@"));
+
+    let bundle = repo.bundle(&["--last-author", "GRACE@example.com"]);
+    assert!(!bundle.contains("@@@@ada.txt@@@@"));
+    assert!(bundle.contains("@@@@shared.txt@@@@"));
+    assert!(bundle.contains("@@@@grace.txt@@@@"));
+}
+
+#[test]
+fn last_author_ignores_merge_commits() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "one\n")
+        .commit("Ada", "ada@example.com", "Add a");
+
+    // A side branch by Grace, merged by Linus
+    let git = Repository::open(repo.path()).unwrap();
+    let base = git.head().unwrap().peel_to_commit().unwrap();
+    repo.write("a.txt", "two\n");
+    let mut index = git.index().unwrap();
+    index.add_path(Path::new("a.txt")).unwrap();
+    let tree = git.find_tree(index.write_tree().unwrap()).unwrap();
+    let grace = Signature::new("Grace", "grace@example.com", &repo.next_commit_time()).unwrap();
+    let side = git
+        .commit(None, &grace, &grace, "Edit a", &tree, &[&base])
+        .unwrap();
+    let side = git.find_commit(side).unwrap();
+    let linus = Signature::new("Linus", "linus@example.com", &repo.next_commit_time()).unwrap();
+    git.commit(
+        Some("HEAD"),
+        &linus,
+        &linus,
+        "Merge",
+        &tree,
+        &[&base, &side],
+    )
+    .unwrap();
+
+    assert!(
+        repo.bundle(&["--last-author", "Grace"])
+            .contains("@@@@a.txt@@@@")
+    );
+    assert!(
+        !repo
+            .bundle(&["--last-author", "Linus"])
+            .contains("@@@@a.txt@@@@")
+    );
+}
+
+#[test]
+fn normalize_unicode_applies_to_bundled_contents() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "cafe\u{301}\u{200B}\n");
+
+    let bundle = repo.bundle(&["--normalize-unicode", "nfc"]);
+
+    assert!(bundle.contains("@@@@a.txt@@@@\ncaf\u{E9}\n"));
+}
+
+#[test]
+fn manifest_only_lists_changes_since_the_previous_manifest() {
+    let repo = TestRepo::new();
+    repo.write("kept.txt", "same\n")
+        .write("edited.txt", "before\n")
+        .write("removed.txt", "gone soon\n");
+    let manifest = repo.outside("manifest.txt");
+    let manifest = manifest.to_str().unwrap();
+    repo.bundle(&["--manifest", manifest]);
+
+    let lines = fs::read_to_string(manifest).unwrap();
+    assert_eq!(lines.lines().count(), 3);
+    assert!(lines.lines().any(|line| line.ends_with(" kept.txt")));
+
+    fs::remove_file(repo.path().join("removed.txt")).unwrap();
+    repo.write("edited.txt", "after\n")
+        .write("added.txt", "new\n");
+    let changes = repo.bundle(&["--manifest-only", manifest]);
+    assert_eq!(changes, "A added.txt\nM edited.txt\nD removed.txt\n");
+}
+
+#[test]
+fn manifest_leaves_out_files_dropped_by_the_token_budget() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "small\n")
+        .write("b.txt", "large ".repeat(2000));
+    let manifest = repo.outside("manifest.txt");
+    let manifest = manifest.to_str().unwrap();
+
+    let bundle = repo.bundle(&["--max-tokens", "200", "--manifest", manifest]);
+
+    assert!(bundle.contains("@@@@a.txt@@@@"));
+    assert!(!bundle.contains("@@@@b.txt@@@@"));
+    let lines = fs::read_to_string(manifest).unwrap();
+    assert!(lines.lines().any(|line| line.ends_with(" a.txt")));
+    assert!(!lines.lines().any(|line| line.ends_with(" b.txt")));
+}
+
+#[test]
+fn tests_after_source_keeps_directories_grouped_under_compact_headers() {
+    let repo = TestRepo::new();
+    repo.write("src/parse.rs", "pub fn parse() {}\n")
+        .write("src/values.rs", "pub fn values() {}\n")
+        .write("tests/parse_test.rs", "#[test] fn t() {}\n");
+
+    let bundle = repo.bundle(&["--tests-after-source", "--deterministic"]);
+    let parse = bundle.find("@@@@src/parse.rs@@@@").unwrap();
+    let test = bundle.find("@@@@tests/parse_test.rs@@@@").unwrap();
+    assert!(parse < test);
+    assert!(!bundle[parse..test].contains("@@@@src/values.rs@@@@"));
+
+    let bundle = repo.bundle(&["--tests-after-source", "--compact-headers"]);
+    assert_eq!(bundle.matches("@@@@src/@@@@\n").count(), 1);
+    assert_eq!(bundle.matches("@@@@tests/@@@@\n").count(), 1);
+    assert!(bundle.contains("@@@@parse_test.rs@@@@"));
+}
+
+#[test]
+fn cache_dir_reuses_entries_until_metadata_changes() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "first\n").write("b.txt", "second\n");
+    let cache = repo.outside("cache");
+    let cache_arg = cache.to_str().unwrap();
+    let cached_run = |extra: &[&str]| {
+        let mut args = vec!["--cache-dir", cache_arg, "--timings"];
+        args.extend(extra);
+        let output = repo.run(&args);
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let cache_line = stderr
+            .lines()
+            .find(|line| line.trim_start().starts_with("cache"))
+            .unwrap_or_default()
+            .split_whitespace()
+            .skip(1)
+            .collect::<Vec<_>>()
+            .join(" ");
+        (String::from_utf8(output.stdout).unwrap(), cache_line)
+    };
+
+    let (bundle, cache_line) = cached_run(&[]);
+    assert_eq!(cache_line, "0 hit(s), 2 miss(es)");
+    assert_eq!(fs::read_dir(&cache).unwrap().count(), 2);
+
+    let (cached_bundle, cache_line) = cached_run(&[]);
+    assert_eq!(cache_line, "2 hit(s), 0 miss(es)");
+    assert_eq!(cached_bundle, bundle);
+
+    // A new modification time misses even with the same contents, and so
+    // does a new size
+    repo.touch("a.txt", 1_600_000_100);
+    assert_eq!(cached_run(&[]).1, "1 hit(s), 1 miss(es)");
+    repo.write("b.txt", "second, longer\n");
+    let (bundle, cache_line) = cached_run(&[]);
+    assert_eq!(cache_line, "1 hit(s), 1 miss(es)");
+    assert!(bundle.contains("@@@@b.txt@@@@\nsecond, longer\n"));
+
+    // Different processing options are cached apart
+    assert_eq!(cached_run(&["--raw"]).1, "0 hit(s), 2 miss(es)");
+}
+
+#[test]
+fn strip_docstrings_only_touches_python_files() {
+    let repo = TestRepo::new();
+    repo.write("a.py", "def f():\n    \"\"\"Docs.\"\"\"\n    return 1\n")
+        .write("a.txt", "\"\"\"Not Python.\"\"\"\n");
+
+    let bundle = repo.bundle(&["--strip-docstrings"]);
+
+    assert!(bundle.contains("@@@@a.py@@@@\ndef f():\n\treturn 1\n"));
+    assert!(bundle.contains("@@@@a.txt@@@@\n\"\"\"Not Python.\"\"\"\n"));
+}
+
+#[test]
+fn repo_root_sets_the_directory_paths_are_relative_to() {
+    let repo = TestRepo::new();
+    repo.write(".gitignore", "*.log\n")
+        .write("top.txt", "top\n")
+        .write("pkg/lib.txt", "lib\n")
+        .write("pkg/debug.log", "ignored\n");
+
+    let bundle = repo.bundle(&["--repo-root", "pkg"]);
+
+    assert!(bundle.contains("@@@@lib.txt@@@@\nlib\n"));
+    assert!(!bundle.contains("top.txt"));
+    assert!(!bundle.contains("debug.log"));
+
+    let outside = repo.outside("elsewhere");
+    fs::create_dir(&outside).unwrap();
+    let output = repo.run(&["--repo-root", outside.to_str().unwrap(), "-r", "."]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is outside the repository"));
+}
+
+#[test]
+fn max_processed_size_measures_contents_after_processing() {
+    let repo = TestRepo::new();
+    // 5 bytes once indentation is trimmed, but 45 on disk
+    repo.write("small.rs", format!("{}x();\n", " ".repeat(40)))
+        .write("large.rs", "large_function_call();\n");
+
+    let bundle = repo.bundle(&["--max-processed-size", "10"]);
+
+    assert!(bundle.contains("@@@@small.rs@@@@\nx();\n"));
+    assert!(!bundle.contains("large.rs"));
+}
+
+#[test]
+fn hints_at_no_gitignore_when_gitignore_skips_everything() {
+    let repo = TestRepo::new();
+    repo.write(".gitignore", "*\n").write("a.txt", "a\n");
+
+    let output = repo.run(&[]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(".gitignore rules skipped 1 file(s)"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("--no-gitignore"));
+
+    let output = repo.run(&["--no-gitignore"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("--no-gitignore"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("@@@@a.txt@@@@"));
+}
+
+#[test]
+fn no_gitignore_hint_when_other_files_are_bundled() {
+    let repo = TestRepo::new();
+    repo.write(".gitignore", "*.log\n")
+        .write("a.txt", "a\n")
+        .write("b.log", "b\n");
+
+    let output = repo.run(&[]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("--no-gitignore"));
+}
+
+#[test]
+fn sample_ext_caps_files_per_extension_and_notes_the_rest() {
+    let repo = TestRepo::new();
+    repo.write("a.json", "{\"a\": 1}\n")
+        .write("b.json", "{\"b\": 2}\n")
+        .write("c.json", "{\"c\": 3}\n")
+        .write("notes.txt", "kept\n");
+
+    let bundle = repo.bundle(&["--sample-ext", "json=1", "--raw"]);
+
+    assert!(bundle.contains("@@@@a.json@@@@"));
+    assert!(!bundle.contains("@@@@b.json@@@@"));
+    assert!(!bundle.contains("@@@@c.json@@@@"));
+    assert!(bundle.contains("@@@@notes.txt@@@@"));
+    let note = bundle
+        .find("Note: 2 more .json file(s) were omitted by --sample-ext\n")
+        .unwrap();
+    assert!(note < bundle.find("@@@@a.json@@@@").unwrap());
+
+    // The note is not taken for part of any file
+    let bundle_path = repo.outside("bundle.txt");
+    let out_dir = repo.outside("out");
+    fs::write(&bundle_path, &bundle).unwrap();
+    let output = repo.run(&[
+        "unbundle",
+        bundle_path.to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(out_dir.join("a.json")).unwrap(),
+        "{\"a\": 1}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(out_dir.join("notes.txt")).unwrap(),
+        "kept\n"
+    );
+}
+
+#[test]
+fn gitignore_rules_apply_like_gitignore_lines() {
+    let repo = TestRepo::new();
+    repo.write(".gitignore", "build/\n")
+        .write("keep.txt", "k\n")
+        .write("gen/out.txt", "o\n")
+        .write("gen/keep.txt", "k\n")
+        .write("build/important.txt", "i\n");
+
+    let bundle = repo.bundle(&[
+        "--gitignore-rule",
+        "gen/*",
+        "--gitignore-rule",
+        "!gen/keep.txt",
+    ]);
+
+    assert!(bundle.contains("@@@@keep.txt@@@@"));
+    assert!(bundle.contains("@@@@gen/keep.txt@@@@"));
+    assert!(!bundle.contains("gen/out.txt"));
+    assert!(!bundle.contains("build/important.txt"));
+}
+
+#[test]
+fn repomap_lists_symbols_before_contents() {
+    let repo = TestRepo::new();
+    repo.write(
+        "lib.rs",
+        "pub struct Config;\n\npub fn load() -> Config {\n    Config\n}\n",
+    );
+
+    let bundle = repo.bundle(&["--repomap"]);
+
+    assert!(
+        bundle.contains("@@@@lib.rs@@@@\nSymbols: struct Config, fn load\npub struct Config;\n")
+    );
+}
+
+#[test]
+fn newer_than_file_keeps_files_modified_after_the_reference() {
+    let repo = TestRepo::new();
+    repo.write("old.txt", "old\n")
+        .write("same.txt", "same\n")
+        .write("new.txt", "new\n")
+        .touch("same.txt", 1_600_000_500)
+        .touch("new.txt", 1_600_001_000);
+    let reference = repo.outside("reference");
+    fs::write(&reference, "").unwrap();
+    common::set_modified(&reference, 1_600_000_500);
+
+    let bundle = repo.bundle(&["--newer-than-file", reference.to_str().unwrap()]);
+
+    assert!(bundle.contains("@@@@new.txt@@@@"));
+    assert!(!bundle.contains("same.txt"));
+    assert!(!bundle.contains("old.txt"));
+}
+
+#[test]
+fn validate_accepts_files_that_round_trip() {
+    let repo = TestRepo::new();
+    repo.write("a.rs", "    indented();\n\n\nblank lines\n")
+        .write("b.txt", "no trailing newline");
+
+    let output = repo.run(&["--validate"]);
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Validated 2 file(s)"));
+}
+
+#[test]
+fn validate_reports_files_that_do_not_round_trip() {
+    let repo = TestRepo::new();
+    repo.write("clean.txt", "clean\n")
+        .write("marker.txt", "before\n@@@@END@@@@\nafter\n")
+        .write("noted.txt", "noted\n")
+        .write("noted.txt.gprepo.md", "A note.\n");
+
+    let output = repo.run(&["--validate"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("marker.txt: "), "{}", stderr);
+    assert!(stderr.contains("noted.txt: contents do not round-trip"));
+    assert!(!stderr.contains("clean.txt"));
+}
+
+#[test]
+fn unbundle_restores_a_raw_bundle() {
+    let repo = TestRepo::new();
+    let files = [
+        ("docs/guide.md", "# Title\n\n    indented\n"),
+        ("src/main.rs", "fn main() {\n    println!(\"hi\");\n}\n"),
+        ("src/deep/er/data.txt", "no trailing newline"),
+    ];
+    for (path, contents) in files {
+        repo.write(path, contents);
+    }
+    let bundle_path = repo.outside("bundle.txt");
+    let out_dir = repo.outside("out");
+
+    for flags in [&["--raw"][..], &["--raw", "--compact-headers"]] {
+        let bundle = repo.bundle(flags);
+        fs::write(&bundle_path, &bundle).unwrap();
+        let output = repo.run(&[
+            "unbundle",
+            bundle_path.to_str().unwrap(),
+            out_dir.to_str().unwrap(),
+        ]);
+        assert!(output.status.success());
+        for (path, contents) in files {
+            assert_eq!(fs::read_to_string(out_dir.join(path)).unwrap(), contents);
+        }
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
+
+#[test]
+fn unbundle_refuses_paths_outside_the_output_directory() {
+    let repo = TestRepo::new();
+    let bundle_path = repo.outside("bundle.txt");
+    let out_dir = repo.outside("out");
+    fs::write(&bundle_path, "@@@@../escaped.txt@@@@\nx\n@@@@END@@@@\n").unwrap();
+
+    let output = repo.run(&[
+        "unbundle",
+        bundle_path.to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+    ]);
+
+    assert!(!output.status.success());
+    assert!(!repo.outside("escaped.txt").exists());
+}
+
+#[test]
+fn unreadable_files_are_reported_without_failing_the_run() {
+    let repo = TestRepo::new();
+    repo.write("good.txt", "good\n")
+        .write("latin1.txt", b"caf\xe9\n");
+
+    let output = repo.run(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("@@@@good.txt@@@@\ngood\n"));
+    assert!(!stdout.contains("latin1.txt"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 file(s) could not be bundled:"));
+    assert!(stderr.contains("  latin1.txt: "));
+
+    let output = repo.run(&["--strict"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to bundle 1 file(s)"));
+}
+
+#[test]
+fn time_budget_stops_adding_files_and_says_so() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n").write("b.txt", "b\n");
+
+    let output = repo.run(&["--time-budget", "0ms"]);
+
+    assert!(output.status.success());
+    let bundle = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        bundle.contains("Note: the time budget ran out; 2 remaining file(s) were not bundled\n")
+    );
+    assert!(!bundle.contains("@@@@a.txt@@@@"));
+    assert!(bundle.ends_with("@@@@END@@@@\n"));
+
+    let bundle = repo.bundle(&["--time-budget", "1h"]);
+    assert!(bundle.contains("@@@@a.txt@@@@"));
+    assert!(!bundle.contains("time budget"));
+}
+
+#[test]
+fn gitignore_hint_is_given_when_the_time_budget_empties_the_bundle() {
+    let repo = TestRepo::new();
+    repo.write(".gitignore", "*.log\n")
+        .write("a.txt", "a\n")
+        .write("b.log", "b\n");
+
+    let output = repo.run(&["--time-budget", "0ms"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No files were included"), "{}", stderr);
+    assert!(stderr.contains("--no-gitignore"));
+}
+
+#[test]
+fn signatures_only_elides_bodies_of_supported_languages() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", "pub fn add(a: u8, b: u8) -> u8 {\n    a + b\n}\n")
+        .write("util.py", "def add(a, b):\n    return a + b\n");
+
+    let bundle = repo.bundle(&["--signatures-only"]);
+
+    assert!(bundle.contains("@@@@lib.rs@@@@\npub fn add(a: u8, b: u8) -> u8 { ... }\n"));
+    assert!(bundle.contains("return a + b"));
+}
+
+#[test]
+fn file_count_header_counts_the_bundled_files() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n")
+        .write("b.txt", "b\n")
+        .write("c.log", "c\n");
+
+    let bundle = repo.bundle(&["--file-count-header", "-e", "*.log"]);
+
+    let preamble_end = bundle.find('\n').unwrap() + 1;
+    assert!(bundle[preamble_end..].starts_with("Files included: 2\n@@@@"));
+    assert!(!repo.bundle(&[]).contains("Files included"));
+}
+
+#[test]
+fn binary_detection_samples_only_the_first_kibibyte() {
+    let repo = TestRepo::new();
+    let mut late_nul = "text\n".repeat(300).into_bytes();
+    late_nul.push(0);
+    let mut early_nul = b"text\n".to_vec();
+    early_nul.push(0);
+    repo.write("late.txt", &late_nul)
+        .write("early.bin", &early_nul);
+
+    let output = repo.run(&[]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("@@@@late.txt@@@@"));
+    assert!(!stdout.contains("early.bin"));
+}
+
+#[test]
+fn excluded_files_are_never_opened_for_binary_detection() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = TestRepo::new();
+    repo.write("keep.txt", "kept\n")
+        .write("secret/locked.txt", "locked contents\n")
+        .write(".gitignore", "*.log\n")
+        .write("debug.log", "ignored contents\n")
+        .write("big.json", "{\"large\": true}\n");
+    let locked = repo.path().join("secret/locked.txt");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let output = repo.run(&["--timings", "-e", "secret", "--size-rule", "*.json:4"]);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o644)).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("@@@@keep.txt@@@@"));
+    for contents in ["locked contents", "ignored contents", "large"] {
+        assert!(!stdout.contains(contents));
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("could not be bundled"), "{}", stderr);
+    assert!(
+        stderr.contains("  binary scans      1 file(s)\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn subtrees_restrict_the_walk_and_keep_root_relative_paths() {
+    let repo = TestRepo::new();
+    repo.write("top.txt", "t\n")
+        .write("a/one.txt", "1\n")
+        .write("a/inner/two.txt", "2\n")
+        .write("b/three.txt", "3\n")
+        .write("c/four.txt", "4\n");
+
+    let bundle = repo.bundle(&["--subtree", "a", "--subtree", "a/inner", "--subtree", "c"]);
+
+    assert_eq!(bundle.matches("@@@@a/inner/two.txt@@@@").count(), 1);
+    assert!(bundle.contains("@@@@a/one.txt@@@@"));
+    assert!(bundle.contains("@@@@c/four.txt@@@@"));
+    assert!(!bundle.contains("top.txt"));
+    assert!(!bundle.contains("three.txt"));
+
+    let output = repo.run(&["--subtree", ".."]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is outside the repository"));
+}
+
+#[test]
+fn notebooks_are_bundled_as_cell_sources() {
+    let repo = TestRepo::new();
+    repo.write(
+        "analysis.ipynb",
+        r#"{"cells": [{"cell_type": "code", "source": ["x = 1\n", "x"],
+            "outputs": [{"output_type": "execute_result", "data": {"text/plain": "1"}}]}]}"#,
+    );
+
+    let bundle = repo.bundle(&[]);
+    assert!(bundle.contains("@@@@analysis.ipynb@@@@\n# %%\nx = 1\nx\n\n@@@@END@@@@"));
+
+    let bundle = repo.bundle(&["--notebook-include-outputs"]);
+    assert!(bundle.contains("# %%\nx = 1\nx\n# Output:\n1\n"));
+}
+
+#[test]
+fn size_rules_cap_only_matching_files() {
+    let repo = TestRepo::new();
+    repo.write("big.json", "x".repeat(2000))
+        .write("small.json", "{}\n")
+        .write("big.txt", "x".repeat(2000));
+
+    let bundle = repo.bundle(&["--size-rule", "*.json:1k"]);
+
+    assert!(!bundle.contains("big.json"));
+    assert!(bundle.contains("@@@@small.json@@@@"));
+    assert!(bundle.contains("@@@@big.txt@@@@"));
+}
+
+#[test]
+fn stdout_holds_only_the_bundle() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n")
+        .write("latin1.txt", b"caf\xe9\n")
+        .write("marker.txt", "@@@@END@@@@\n");
+
+    let output = repo.run(&["--timings", "--eol-report"]);
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Below is a repository"));
+    assert!(stdout.ends_with("@@@@END@@@@\n"));
+    assert!(!stdout.contains("Warning"));
+    assert!(!stdout.contains("could not be bundled"));
+    assert!(!stdout.contains("Timings"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning"));
+    assert!(stderr.contains("could not be bundled"));
+    assert!(stderr.contains("Timings"));
+
+    assert_eq!(repo.run(&["-o", "-"]).stdout, repo.run(&[]).stdout);
+    assert!(!repo.path().join("-").exists());
+}
+
+#[test]
+fn max_files_per_dir_keeps_the_first_files_of_each_directory() {
+    let repo = TestRepo::new();
+    repo.write("src/a.rs", "a\n")
+        .write("src/b.rs", "b\n")
+        .write("src/c.rs", "c\n")
+        .write("src/nested/d.rs", "d\n")
+        .write("e.txt", "e\n");
+
+    let bundle = repo.bundle(&["--max-files-per-dir", "2"]);
+
+    assert!(bundle.contains("@@@@src/a.rs@@@@"));
+    assert!(bundle.contains("@@@@src/b.rs@@@@"));
+    assert!(!bundle.contains("src/c.rs"));
+    assert!(bundle.contains("@@@@src/nested/d.rs@@@@"));
+    assert!(bundle.contains("@@@@e.txt@@@@"));
+    assert!(bundle.contains("Note: 1 more file(s) in src/ were omitted by --max-files-per-dir\n"));
+}
+
+#[test]
+fn timings_reports_every_phase_on_stderr() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+
+    let output = repo.run(&["--timings"]);
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for phase in [
+        "walking",
+        "filtering",
+        "binary detection",
+        "reading",
+        "processing",
+        "writing",
+    ] {
+        assert!(stderr.contains(&format!("  {}", phase)), "{}", stderr);
+    }
+    assert_eq!(output.stdout, repo.run(&[]).stdout);
+}
+
+#[test]
+fn eol_report_lists_crlf_and_mixed_files() {
+    let repo = TestRepo::new();
+    repo.write("lf.txt", "a\nb\n")
+        .write("crlf.txt", "a\r\nb\r\n")
+        .write("mixed.txt", "a\r\nb\n");
+
+    let output = repo.run(&["--eol-report"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("2 file(s) do not use LF line endings:"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("  crlf.txt: CRLF\n"));
+    assert!(stderr.contains("  mixed.txt: mixed CRLF and LF\n"));
+    assert!(!stderr.contains("  lf.txt:"));
+    assert!(!String::from_utf8_lossy(&repo.run(&[]).stderr).contains("line endings"));
+}
+
+#[test]
+fn dirty_keeps_files_with_working_tree_changes() {
+    let repo = TestRepo::new();
+    repo.write("clean.txt", "clean\n")
+        .write("modified.txt", "before\n")
+        .write("staged.txt", "before\n")
+        .commit("Ada", "ada@example.com", "Add files");
+    repo.write("modified.txt", "after\n")
+        .write("staged.txt", "after\n")
+        .write("untracked.txt", "new\n");
+    let git = Repository::open(repo.path()).unwrap();
+    let mut index = git.index().unwrap();
+    index.add_path(Path::new("staged.txt")).unwrap();
+    index.write().unwrap();
+
+    let bundle = repo.bundle(&["--dirty"]);
+
+    assert!(bundle.contains("@@@@modified.txt@@@@\nafter\n"));
+    assert!(bundle.contains("@@@@untracked.txt@@@@"));
+    assert!(!bundle.contains("clean.txt"));
+    assert!(!bundle.contains("staged.txt"));
+}
+
+#[test]
+fn chunks_split_the_bundle_into_self_contained_files() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a".repeat(300))
+        .write("b.txt", "b".repeat(200))
+        .write("c.txt", "c".repeat(100));
+    let output = repo.outside("bundle.txt");
+
+    repo.bundle(&["--chunks", "2", "-o", output.to_str().unwrap()]);
+
+    assert!(!output.exists());
+    let first = fs::read_to_string(repo.outside("bundle.1.txt")).unwrap();
+    let second = fs::read_to_string(repo.outside("bundle.2.txt")).unwrap();
+    for chunk in [&first, &second] {
+        assert!(chunk.starts_with("Below is a repository"));
+        assert!(chunk.ends_with("@@@@END@@@@\n"));
+    }
+    assert!(first.contains("@@@@a.txt@@@@"));
+    assert!(second.contains("@@@@b.txt@@@@"));
+    assert!(second.contains("@@@@c.txt@@@@"));
+    assert!(!repo.outside("bundle.3.txt").exists());
+
+    let output = repo.run(&["--chunks", "2"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn path_base_sets_the_directory_paths_are_emitted_from() {
+    let repo = TestRepo::new();
+    repo.write("top.txt", "t\n").write("pkg/src/lib.rs", "l\n");
+
+    // Above the root
+    let bundle = repo.bundle(&["--repo-root", "pkg", "--path-base", "."]);
+    assert!(bundle.contains("@@@@pkg/src/lib.rs@@@@"));
+
+    // Below the root, with files outside it keeping root-relative paths
+    let bundle = repo.bundle(&["--path-base", "pkg"]);
+    assert!(bundle.contains("@@@@src/lib.rs@@@@"));
+    assert!(bundle.contains("@@@@top.txt@@@@"));
+
+    let elsewhere = repo.outside("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+    let output = repo.run(&["--path-base", elsewhere.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("neither contains nor is inside"));
+}
+
+#[test]
+fn path_base_refuses_files_that_would_share_an_emitted_path() {
+    let repo = TestRepo::new();
+    repo.write("src/lib.rs", "outer\n")
+        .write("pkg/src/lib.rs", "inner\n");
+
+    let output = repo.run(&["--path-base", "pkg"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("would both be emitted as src/lib.rs"),
+        "{}",
+        stderr
+    );
+    assert!(output.stdout.is_empty());
+
+    let bundle = repo.bundle(&["--path-base", "pkg", "-i", "pkg"]);
+    assert!(bundle.contains("@@@@src/lib.rs@@@@\ninner\n"));
+}
+
+#[test]
+fn from_archive_bundles_a_tarball_with_its_gitignore() {
+    let repo = TestRepo::new();
+    let archive_path = repo.outside("snapshot.tar.gz");
+    let encoder = GzEncoder::new(
+        fs::File::create(&archive_path).unwrap(),
+        Compression::default(),
+    );
+    let mut builder = tar::Builder::new(encoder);
+    for (path, contents) in [
+        (".gitignore", "*.log\n"),
+        ("src/lib.rs", "pub fn f() {}\n"),
+        ("debug.log", "ignored\n"),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, contents.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+
+    let bundle = repo.bundle(&["--from-archive", archive_path.to_str().unwrap()]);
+
+    assert!(bundle.contains("@@@@src/lib.rs@@@@\npub fn f() {}\n"));
+    assert!(!bundle.contains("debug.log"));
+}
+
+#[test]
+fn entrypoints_first_moves_entrypoints_to_the_front() {
+    let repo = TestRepo::new();
+    repo.write("a.rs", "a\n")
+        .write("b/app.py", "b\n")
+        .write("src/main.rs", "m\n");
+
+    let bundle = repo.bundle(&["--entrypoints-first", "--deterministic"]);
+    assert!(bundle.find("@@@@src/main.rs@@@@").unwrap() < bundle.find("@@@@a.rs@@@@").unwrap());
+
+    let bundle = repo.bundle(&[
+        "--entrypoints-first",
+        "--entrypoint",
+        "app.py",
+        "--deterministic",
+    ]);
+    assert!(bundle.find("@@@@b/app.py@@@@").unwrap() < bundle.find("@@@@a.rs@@@@").unwrap());
+    assert!(bundle.find("@@@@a.rs@@@@").unwrap() < bundle.find("@@@@src/main.rs@@@@").unwrap());
+}
+
+#[test]
+fn shared_file_names_warn_and_can_fail() {
+    let repo = TestRepo::new();
+    repo.write("client/utils.rs", "c\n")
+        .write("server/utils.rs", "s\n")
+        .write("main.rs", "m\n");
+
+    for args in [&[][..], &["--compact-headers"]] {
+        let output = repo.run(args);
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("1 file name(s) are shared by several included files:"));
+        assert!(
+            stderr.contains("  utils.rs: client/utils.rs, server/utils.rs"),
+            "{}",
+            stderr
+        );
+    }
+
+    let output = repo.run(&["-e", "server"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("shared"));
+
+    let output_path = repo.outside("bundle.txt");
+    let output = repo.run(&["--fail-on-collision", "-o", output_path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("utils.rs"));
+    assert!(!output_path.exists());
+}
+
+#[test]
+fn binary_threshold_extremes() {
+    let repo = TestRepo::new();
+    repo.write("plain.txt", "plain text\n")
+        .write("one.txt", "bell\x07\n")
+        .write("half.txt", "\x01\x02\x03\x04\x05ab\ncd\n")
+        .write("nul.txt", "nul\0\n");
+
+    let bundle = repo.bundle(&[]);
+    assert!(bundle.contains("@@@@plain.txt@@@@"));
+    assert!(bundle.contains("@@@@one.txt@@@@"));
+    assert!(!bundle.contains("half.txt"));
+    assert!(!bundle.contains("nul.txt"));
+
+    let bundle = repo.bundle(&["--binary-threshold", "0"]);
+    assert!(bundle.contains("@@@@plain.txt@@@@"));
+    assert!(!bundle.contains("one.txt"));
+
+    let bundle = repo.bundle(&["--binary-threshold", "1"]);
+    assert!(bundle.contains("@@@@half.txt@@@@"));
+    assert!(!bundle.contains("nul.txt"));
+
+    assert!(!repo.run(&["--binary-threshold", "2"]).status.success());
+}
+
+#[test]
+fn empty_files_are_emitted_unless_skipped() {
+    let repo = TestRepo::new();
+    repo.write("empty.txt", "")
+        .write("blank.rs", "   \n\n")
+        .write("full.txt", "x\n");
+
+    let bundle = repo.bundle(&[]);
+    assert!(bundle.contains("@@@@empty.txt@@@@\n\n"));
+    assert!(bundle.contains("@@@@blank.rs@@@@\n\n"));
+
+    let bundle = repo.bundle(&["--skip-empty-files"]);
+    assert!(!bundle.contains("empty.txt"));
+    assert!(!bundle.contains("blank.rs"));
+    assert!(bundle.contains("@@@@full.txt@@@@"));
+
+    // The last of the two flags wins
+    let bundle = repo.bundle(&["--skip-empty-files", "--include-empty-files"]);
+    assert!(bundle.contains("@@@@empty.txt@@@@"));
+}
+
+#[test]
+fn path_prefixed_lines_read_like_grep_output() {
+    let repo = TestRepo::new();
+    repo.write("src/lib.rs", "fn a() {}\nfn b() {}\n");
+
+    let bundle = repo.bundle(&["--path-prefixed-lines"]);
+
+    assert!(
+        bundle.contains("@@@@src/lib.rs@@@@\nsrc/lib.rs:1: fn a() {}\nsrc/lib.rs:2: fn b() {}\n")
+    );
+
+    // Compact headers shorten the block header, but not the line prefixes
+    let bundle = repo.bundle(&["--path-prefixed-lines", "--compact-headers"]);
+    assert!(bundle.contains("@@@@lib.rs@@@@\nsrc/lib.rs:1: fn a() {}\n"));
+}
+
+#[test]
+fn split_index_lists_the_files_of_each_chunk() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a".repeat(300))
+        .write("b.txt", "b".repeat(200))
+        .write("c.txt", "c".repeat(100));
+    let output = repo.outside("bundle.txt");
+    let index_path = repo.outside("index.json");
+
+    repo.bundle(&[
+        "--chunks",
+        "2",
+        "-o",
+        output.to_str().unwrap(),
+        "--split-index",
+        index_path.to_str().unwrap(),
+    ]);
+
+    let index: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&index_path).unwrap()).unwrap();
+    let chunks = index.as_object().unwrap();
+    assert_eq!(chunks.len(), 2);
+    let first = repo.outside("bundle.1.txt");
+    let second = repo.outside("bundle.2.txt");
+    assert_eq!(
+        chunks[first.to_str().unwrap()],
+        serde_json::json!(["a.txt"])
+    );
+    let rest = chunks[second.to_str().unwrap()].as_array().unwrap();
+    assert_eq!(rest.len(), 2);
+    for path in rest {
+        let header = format!("@@@@{}@@@@", path.as_str().unwrap());
+        assert!(fs::read_to_string(&second).unwrap().contains(&header));
+    }
+}
+
+#[test]
+fn force_include_bundles_gitignored_binaries_as_base64() {
+    let repo = TestRepo::new();
+    repo.write(".gitignore", "assets/\n")
+        .write("assets/logo.png", b"\x89PNG\0\x01")
+        .write("assets/other.png", b"\x89PNG\0\x02");
+
+    let bundle = repo.bundle(&["--force-include", "assets/logo.png"]);
+
+    assert!(bundle.contains(
+        "@@@@assets/logo.png@@@@\nNote: binary file; contents are base64-encoded\niVBORwAB\n"
+    ));
+    assert!(!bundle.contains("other.png"));
+}
+
+#[test]
+fn force_include_survives_the_token_budget_but_not_self_output() {
+    let repo = TestRepo::new();
+    repo.write("big.txt", "word ".repeat(4000))
+        .write("small.txt", "small\n")
+        .write("out/previous.txt", "old bundle\n");
+
+    let output = repo.path().join("out/previous.txt");
+    repo.bundle(&[
+        "--force-include",
+        "*.txt",
+        "--force-include",
+        "out/*",
+        "--max-tokens",
+        "100",
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+
+    let bundle = fs::read_to_string(&output).unwrap();
+    assert!(bundle.contains("@@@@big.txt@@@@"));
+    assert!(bundle.contains("@@@@small.txt@@@@"));
+    assert!(!bundle.contains("out/previous.txt"));
+}
+
+#[test]
+fn repo_summary_describes_the_history() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+    assert!(
+        repo.bundle(&["--with-repo-summary"])
+            .contains("Repository summary: no commits yet.\n")
+    );
+
+    repo.commit("Ada", "ada@example.com", "Add a");
+    repo.write("b.txt", "b\n")
+        .commit("Grace", "grace@example.com", "Add b");
+    repo.write("c.txt", "c\n")
+        .commit("Ada", "ada@example.com", "Add c");
+    let git = Repository::open(repo.path()).unwrap();
+    let branch = git.head().unwrap().shorthand().unwrap().to_string();
+
+    let bundle = repo.bundle(&["--with-repo-summary"]);
+    let expected = format!(
+        "Repository summary:\n- Default branch: {}\n- Commits: 3\n- Contributors: 2\n- Latest commit: Add c\n",
+        branch
+    );
+    assert!(bundle.starts_with(&expected), "{}", bundle);
+}
+
+#[test]
+fn repo_summary_prefers_the_remote_default_branch() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n")
+        .commit("Ada", "ada@example.com", "Add a");
+    let git = Repository::open(repo.path()).unwrap();
+    let head = git.head().unwrap().peel_to_commit().unwrap();
+    git.reference("refs/remotes/origin/trunk", head.id(), false, "test")
+        .unwrap();
+    git.reference_symbolic(
+        "refs/remotes/origin/HEAD",
+        "refs/remotes/origin/trunk",
+        false,
+        "test",
+    )
+    .unwrap();
+    git.branch("feature", &head, false).unwrap();
+    git.set_head("refs/heads/feature").unwrap();
+
+    let bundle = repo.bundle(&["--with-repo-summary"]);
+
+    assert!(bundle.contains("- Default branch: trunk\n"), "{}", bundle);
+}
+
+#[test]
+fn max_tokens_keeps_the_rendered_bundle_within_budget() {
+    let repo = TestRepo::new();
+    for (index, size) in [40, 400, 120, 800, 60].into_iter().enumerate() {
+        repo.write(&format!("dir/file{}.txt", index), "x".repeat(size));
+    }
+    let output = repo.outside("bundle.txt");
+    let output_arg = output.to_str().unwrap();
+
+    for budget in [150, 250, 400, 700] {
+        for flags in [&[][..], &["--compact-headers", "--path-prefixed-lines"]] {
+            let budget_arg = budget.to_string();
+            let mut args = vec!["--max-tokens", &budget_arg, "-o", output_arg];
+            args.extend(flags);
+            repo.bundle(&args);
+
+            let cost = repo.bundle(&["cost", output_arg]);
+            let tokens: usize = cost
+                .lines()
+                .find_map(|line| line.strip_prefix("Estimated tokens: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(
+                tokens <= budget,
+                "{} tokens for a budget of {}",
+                tokens,
+                budget
+            );
+            let bundle = fs::read_to_string(&output).unwrap();
+            assert!(bundle.contains("Included "));
+            if budget >= 400 {
+                assert!(!bundle.contains("Included 0 file(s)"));
+            }
+        }
+    }
+
+    let bundle = repo.bundle(&["--max-tokens", "100000"]);
+    assert!(bundle.contains("Included 5 file(s), skipped 0 for the token budget"));
+}
+
+#[test]
+fn module_order_emits_rust_modules_before_their_users() {
+    let repo = TestRepo::new();
+    repo.write(
+        "src/main.rs",
+        "mod app;\nmod config;\nfn main() { app::run() }\n",
+    )
+    .write(
+        "src/app.rs",
+        "use crate::config::Config;\npub fn run() {}\n",
+    )
+    .write("src/config.rs", "pub struct Config;\n")
+    .write("notes.txt", "n\n");
+
+    let bundle = repo.bundle(&["--module-order", "--deterministic"]);
+
+    let position = |path: &str| bundle.find(&format!("@@@@{}@@@@", path)).unwrap();
+    assert!(position("src/config.rs") < position("src/app.rs"));
+    assert!(position("src/app.rs") < position("src/main.rs"));
+    // Other files keep their place
+    assert!(position("notes.txt") < position("src/config.rs"));
+}
+
+#[test]
+fn head_tail_shortens_only_long_files() {
+    let repo = TestRepo::new();
+    let long: String = (1..=10).map(|line| format!("line {}\n", line)).collect();
+    repo.write("long.txt", &long)
+        .write("short.txt", "a\nb\nc\nd\n");
+
+    let bundle = repo.bundle(&["--head-tail", "2"]);
+
+    assert!(bundle.contains(
+        "@@@@long.txt@@@@\nline 1\nline 2\n[... 6 lines omitted ...]\nline 9\nline 10\n"
+    ));
+    assert!(bundle.contains("@@@@short.txt@@@@\na\nb\nc\nd\n"));
+}
+
+#[test]
+fn manifests_first_emits_cargo_toml_first_and_verbatim() {
+    let repo = TestRepo::new();
+    repo.write("a.rs", "fn a() {}\n")
+        .write(
+            "Cargo.toml",
+            "[package]\nname = \"demo\"\n\n[dependencies]\n  serde = \"1\"\n",
+        )
+        .write("other.toml", "  indented = true\n");
+
+    let bundle = repo.bundle(&["--manifests-first", "--deterministic"]);
+
+    let preamble_end = bundle.find('\n').unwrap() + 1;
+    assert!(bundle[preamble_end..].starts_with(
+        "@@@@Cargo.toml@@@@\n[package]\nname = \"demo\"\n\n[dependencies]\n  serde = \"1\"\n"
+    ));
+    assert!(bundle.contains("@@@@other.toml@@@@\nindented = true\n"));
+}
+
+#[test]
+fn summarize_lockfiles_notes_the_dependency_count() {
+    let repo = TestRepo::new();
+    repo.write(
+        "Cargo.lock",
+        "[[package]]\nname = \"a\"\n\n[[package]]\nname = \"b\"\n",
+    )
+    .write("sub/Cargo.lock", "[[package]]\nname = \"c\"\n")
+    .write("flake.lock", "{}\n")
+    .write("src/lib.rs", "pub fn f() {}\n");
+
+    let bundle = repo.bundle(&["--summarize-lockfiles", "-e", "sub/*"]);
+
+    assert!(bundle.contains("Note: Cargo.lock: 2 dependencies\n"));
+    assert!(!bundle.contains("@@@@Cargo.lock@@@@"));
+    assert!(!bundle.contains("sub/Cargo.lock"));
+    assert!(!bundle.contains("flake.lock"));
+    assert!(!bundle.contains("name = \"a\""));
+
+    // Lockfiles are skipped entirely by default
+    assert!(!repo.bundle(&[]).contains("Cargo.lock"));
+}
+
+#[test]
+fn ascii_only_rewrites_contents_but_not_paths() {
+    let repo = TestRepo::new();
+    repo.write("caf\u{E9}.txt", "cr\u{E8}me br\u{FB}l\u{E9}e\n")
+        .write("plain.txt", "plain\n");
+
+    let output = repo.run(&["--ascii-only"]);
+    let bundle = String::from_utf8_lossy(&output.stdout);
+    assert!(bundle.contains("@@@@caf\u{E9}.txt@@@@\ncr?me br?l?e\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--ascii-only changed 3 non-ASCII character(s) in 1 file(s)"));
+
+    let bundle = repo.bundle(&["--ascii-only=strip"]);
+    assert!(bundle.contains("\ncrme brle\n"));
+}
+
+#[test]
+fn hash_filename_is_stable_and_skips_earlier_bundles() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+    let output = repo.path().join("bundle.txt");
+    let args = ["--hash-filename", "-o", output.to_str().unwrap()];
+
+    let bundled = |repo: &TestRepo| {
+        let output = repo.run(&args);
+        assert!(output.status.success());
+        let mut names: Vec<String> = fs::read_dir(repo.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("bundle."))
+            .collect();
+        names.sort();
+        names
+    };
+    let first = bundled(&repo);
+    assert_eq!(first.len(), 1);
+    let name = &first[0];
+    let hash = &name["bundle.".len()..name.len() - ".txt".len()];
+    let contents = fs::read(repo.path().join(name)).unwrap();
+    let blob = git2::Oid::hash_object(git2::ObjectType::Blob, &contents).unwrap();
+    assert_eq!(hash, &blob.to_string()[..12]);
+    assert!(!String::from_utf8_lossy(&contents).contains("@@@@bundle."));
+
+    // The earlier bundle is not bundled again, so the hash does not change
+    assert_eq!(bundled(&repo), first);
+}
+
+#[test]
+fn exclude_test_data_skips_fixture_directories() {
+    let repo = TestRepo::new();
+    repo.write("src/lib.rs", "l\n")
+        .write("tests/testdata/input.txt", "i\n")
+        .write("src/__snapshots__/lib.snap", "s\n")
+        .write("samples/case.txt", "c\n")
+        .write("src/testdata.rs", "not a directory\n");
+
+    let bundle = repo.bundle(&["--exclude-test-data"]);
+    assert!(bundle.contains("@@@@src/lib.rs@@@@"));
+    assert!(bundle.contains("@@@@src/testdata.rs@@@@"));
+    assert!(bundle.contains("@@@@samples/case.txt@@@@"));
+    assert!(!bundle.contains("input.txt"));
+    assert!(!bundle.contains("lib.snap"));
+
+    let bundle = repo.bundle(&["--exclude-test-data", "--test-data-dir", "samples"]);
+    assert!(!bundle.contains("case.txt"));
+    assert!(bundle.contains("@@@@tests/testdata/input.txt@@@@"));
+}
+
+#[test]
+fn merge_under_combines_small_files_and_unbundles_back() {
+    let repo = TestRepo::new();
+    repo.write("src/a.rs", "a\n")
+        .write("src/b.rs", "b\n")
+        .write("src/big.rs", format!("{}\n", "x".repeat(200)))
+        .write("lone.txt", "l\n");
+
+    let bundle = repo.bundle(&["--merge-under", "100", "--raw"]);
+    assert!(bundle.contains("@@@@src/*@@@@\n--- a.rs ---\na\n\n--- b.rs ---\nb\n"));
+    assert!(bundle.contains("@@@@src/big.rs@@@@"));
+    assert!(bundle.contains("@@@@lone.txt@@@@"));
+
+    let bundle_path = repo.outside("bundle.txt");
+    let out_dir = repo.outside("out");
+    fs::write(&bundle_path, &bundle).unwrap();
+    let output = repo.run(&[
+        "unbundle",
+        bundle_path.to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    for path in ["src/a.rs", "src/b.rs", "src/big.rs", "lone.txt"] {
+        assert_eq!(
+            fs::read(out_dir.join(path)).unwrap(),
+            fs::read(repo.path().join(path)).unwrap()
+        );
+    }
+}
+
+#[test]
+fn diagnostics_are_colored_only_on_request() {
+    let repo = TestRepo::new();
+    repo.write("latin1.txt", b"caf\xe9\n").write("a.txt", "a\n");
+
+    for args in [&["--color", "never"][..], &[], &["--color", "auto"]] {
+        let output = repo.run(args);
+        assert!(!output.stderr.is_empty());
+        assert!(!output.stderr.contains(&0x1b), "{:?}", args);
+        assert!(!output.stdout.contains(&0x1b));
+    }
+
+    let output = repo.run(&["--color", "always"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\x1b[31m"));
+    assert!(!output.stdout.contains(&0x1b));
+}
+
+#[test]
+fn cost_prices_a_bundle_with_a_pricing_file() {
+    let repo = TestRepo::new();
+    let bundle_path = repo.outside("bundle.txt");
+    fs::write(&bundle_path, "x".repeat(4_000_000)).unwrap();
+    let pricing = repo.outside("pricing.txt");
+    fs::write(&pricing, "# model price\nbig model $3.00\n\ncheap 0.001\n").unwrap();
+
+    let output = repo.bundle(&[
+        "cost",
+        bundle_path.to_str().unwrap(),
+        "--pricing",
+        pricing.to_str().unwrap(),
+    ]);
+
+    assert_eq!(
+        output,
+        "Estimated tokens: 1000000\nbig model: ~$3.00\ncheap: ~$0.0010\n"
+    );
+
+    fs::write(&pricing, "no price here\n").unwrap();
+    let output = repo.run(&[
+        "cost",
+        bundle_path.to_str().unwrap(),
+        "--pricing",
+        pricing.to_str().unwrap(),
+    ]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn deterministic_output_is_identical_across_checkouts() {
+    let files = [
+        ("b/z.txt", "z\n"),
+        ("a.txt", "a\n"),
+        ("b/a.txt", "ba\n"),
+        ("c.rs", "fn c() {}\n"),
+        // Ordered differently by locale-aware collation
+        ("Z.txt", "upper\n"),
+        ("\u{e4}.txt", "umlaut\n"),
+        ("i.txt", "dotted\n"),
+        ("I.txt", "dotless\n"),
+    ];
+    let first = TestRepo::new();
+    for (path, contents) in files {
+        first.write(path, contents);
+    }
+    let second = TestRepo::new();
+    for (path, contents) in files.iter().rev() {
+        second.write(path, contents);
+    }
+    // Modified "after" the run started, as with a clock that is ahead
+    second.touch("c.rs", 4_000_000_000);
+
+    let bundle = first.bundle(&["--deterministic"]);
+    assert_eq!(bundle, second.bundle(&["--deterministic"]));
+    assert!(bundle.contains("@@@@c.rs@@@@"));
+
+    // As if on a machine with another locale and time zone
+    for vars in [
+        [("LC_ALL", "C"), ("LANG", "C"), ("TZ", "UTC")],
+        [
+            ("LC_ALL", "tr_TR.UTF-8"),
+            ("LANG", "tr_TR.UTF-8"),
+            ("TZ", "Pacific/Kiritimati"),
+        ],
+        [
+            ("LC_ALL", "sv_SE.UTF-8"),
+            ("LANG", "de_DE.UTF-8"),
+            ("TZ", "America/St_Johns"),
+        ],
+    ] {
+        let output = second.run_with_env(&["--deterministic"], &vars);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            bundle,
+            "{:?}",
+            vars
+        );
+    }
+
+    assert!(
+        !first
+            .run(&["--deterministic", "--time-budget", "1s"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn preamble_in_first_writes_the_preamble_once() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a".repeat(300))
+        .write("b.txt", "b".repeat(200))
+        .write("c.txt", "c".repeat(100));
+    let preamble = repo.outside("preamble.txt");
+    fs::write(&preamble, "Review these files.\n").unwrap();
+    let output = repo.outside("bundle.txt");
+
+    repo.bundle(&[
+        "--chunks",
+        "3",
+        "--preamble-in",
+        "first",
+        "-p",
+        preamble.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+
+    let chunks: Vec<String> = (1..=3)
+        .map(|index| fs::read_to_string(repo.outside(&format!("bundle.{}.txt", index))).unwrap())
+        .collect();
+    assert!(chunks[0].starts_with("Review these files.\n\n@@@@"));
+    assert!(chunks[1].starts_with("(continued, chunk 2/3)\n@@@@"));
+    assert!(chunks[2].starts_with("(continued, chunk 3/3)\n@@@@"));
+    let all = chunks.concat();
+    assert_eq!(all.matches("Review these files.").count(), 1);
+}
+
+#[test]
+fn marker_lines_warn_and_strict_markers_refuses_to_write() {
+    let repo = TestRepo::new();
+    repo.write("ok.txt", "plain\n")
+        .write("quoted.txt", "before\n@@@@END@@@@\nafter\n")
+        .write("noted.txt", "plain too\n")
+        .write("noted.txt.gprepo.md", "@@@@other.txt@@@@\n");
+
+    let output = repo.run(&[]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 file(s) contain a line that reads as a bundle marker"));
+    assert!(stderr.contains("  quoted.txt"));
+    assert!(stderr.contains("  noted.txt"));
+    assert!(!stderr.contains("  ok.txt"));
+
+    let bundle = repo.outside("bundle.txt");
+    fs::write(&bundle, "previous bundle\n").unwrap();
+    let output = repo.run(&["--strict-markers", "-o", bundle.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(fs::read_to_string(&bundle).unwrap(), "previous bundle\n");
+}
+
+#[test]
+fn require_files_fails_without_touching_the_output() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n");
+    let bundle = repo.outside("bundle.txt");
+    fs::write(&bundle, "previous bundle\n").unwrap();
+
+    let output = repo.run(&[
+        "--require-files",
+        "-i",
+        "nothing",
+        "-o",
+        bundle.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No files would be included"));
+    assert_eq!(fs::read_to_string(&bundle).unwrap(), "previous bundle\n");
+
+    let output = repo.run(&["--require-files"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn with_notes_prepends_the_note_on_head() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a\n")
+        .commit("Ada", "ada@example.com", "Add a");
+
+    let bundle = repo.bundle(&["--with-notes"]);
+    assert!(!bundle.contains("Notes on the latest commit"));
+
+    let git = Repository::open(repo.path()).unwrap();
+    let head = git.head().unwrap().peel_to_commit().unwrap().id();
+    let ada = Signature::new("Ada", "ada@example.com", &repo.next_commit_time()).unwrap();
+    git.note(&ada, &ada, None, head, "Reviewed; ship it.\n", false)
+        .unwrap();
+
+    let bundle = repo.bundle(&["--with-notes"]);
+    assert!(bundle.starts_with("Notes on the latest commit:\nReviewed; ship it.\nBelow is"));
+    assert!(!repo.bundle(&[]).contains("Notes on the latest commit"));
+}
+
+#[test]
+fn progressive_narrows_files_by_distance_from_the_focus() {
+    let repo = TestRepo::new();
+    repo.write("src/core/engine.rs", "pub fn run() {\n    let x = 1;\n}\n")
+        .write("src/lib.rs", "pub fn parse() -> u8 {\n    7\n}\n")
+        .write("src/cli/args.rs", "pub fn main_cli() {\n    go();\n}\n")
+        .write("docs/guide.md", "guide\n");
+
+    let bundle = repo.bundle(&["--progressive", "--focus", "src/core"]);
+    assert!(bundle.contains("@@@@src/core/engine.rs@@@@\npub fn run() {\nlet x = 1;\n}\n"));
+    assert!(bundle.contains("@@@@src/lib.rs@@@@\npub fn parse() -> u8 { ... }\n"));
+    let omitted = "Note: far from the focus path; contents omitted\n\n";
+    assert!(bundle.contains(&format!("@@@@src/cli/args.rs@@@@\n{}", omitted)));
+    assert!(bundle.contains(&format!("@@@@docs/guide.md@@@@\n{}", omitted)));
+
+    // A focus file stands for the directory holding it
+    let by_file = repo.bundle(&["--progressive", "--focus", "src/core/engine.rs"]);
+    assert_eq!(by_file, bundle);
+}
+
+#[test]
+fn merge_under_keeps_the_paths_of_merged_files() {
+    let repo = TestRepo::new();
+    repo.write("src/a.rs", "a\n")
+        .write("src/b.rs", "b1\nb2\n")
+        .write("src/big.rs", format!("{}\n", "x".repeat(200)))
+        .write("lone.txt", "l\n");
+
+    let bundle = repo.bundle(&["--merge-under", "100", "--file-count-header"]);
+    assert!(bundle.contains("Files included: 4\n"));
+
+    let bundle = repo.bundle(&["--merge-under", "100", "--path-prefixed-lines"]);
+    assert!(bundle.contains(
+        "@@@@src/*@@@@\n--- a.rs ---\nsrc/a.rs:1: a\n--- b.rs ---\nsrc/b.rs:1: b1\nsrc/b.rs:2: b2\n"
+    ));
+
+    let output = repo.outside("bundle.txt");
+    let index = repo.outside("index.json");
+    repo.bundle(&[
+        "--merge-under",
+        "100",
+        "--chunks",
+        "1",
+        "-o",
+        output.to_str().unwrap(),
+        "--split-index",
+        index.to_str().unwrap(),
+    ]);
+    let index: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&index).unwrap()).unwrap();
+    let chunk = repo.outside("bundle.1.txt");
+    let mut paths: Vec<&str> = index[chunk.to_str().unwrap()]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|path| path.as_str().unwrap())
+        .collect();
+    paths.sort();
+    assert_eq!(paths, ["lone.txt", "src/a.rs", "src/b.rs", "src/big.rs"]);
+}
+
+#[test]
+fn merge_under_treats_file_delimiters_in_contents_as_markers() {
+    let repo = TestRepo::new();
+    repo.write("src/a.rs", "a\n")
+        .write("src/c.txt", "x\n--- b.rs ---\ny\n");
+
+    let output = repo.run(&["--merge-under", "100", "--strict-markers"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("  src/c.txt"));
+
+    // Without merging, the line is ordinary contents
+    let output = repo.run(&["--strict-markers"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn parallel_walk_matches_the_deterministic_serial_walk() {
+    let repo = TestRepo::new();
+    for path in [
+        "a.txt",
+        "b.txt",
+        "b/a.txt",
+        "b/z.txt",
+        "b/c/d.txt",
+        "b-x/y.txt",
+        "c.rs",
+    ] {
+        repo.write(path, format!("{}\n", path));
+    }
+
+    let serial = repo.bundle(&["--deterministic"]);
+    assert_eq!(repo.bundle(&["--deterministic", "--parallel-walk"]), serial);
+    assert_eq!(repo.bundle(&["--parallel-walk"]), serial);
+    assert_eq!(serial.matches("@@@@\n").count(), 8);
+}
+
+#[test]
+fn sidecar_notes_are_kept_alongside_generated_notes() {
+    let repo = TestRepo::new();
+    repo.write("logo.png", b"\x89PNG\0\x01")
+        .write("logo.png.gprepo.md", "The current logo.\n")
+        .write("core/lib.rs", "pub fn run() {}\n")
+        .write("far/away/notes.txt", "n\n")
+        .write("far/away/notes.txt.gprepo.md", "Kept for history.\n");
+
+    let bundle = repo.bundle(&["--force-include", "*.png"]);
+    assert!(bundle.contains(
+        "@@@@logo.png@@@@\nNote: The current logo.\nNote: binary file; contents are base64-encoded\n"
+    ));
+
+    let bundle = repo.bundle(&["--progressive", "--focus", "core"]);
+    assert!(bundle.contains(
+        "@@@@far/away/notes.txt@@@@\nNote: Kept for history.\nNote: far from the focus path; contents omitted\n"
+    ));
+}
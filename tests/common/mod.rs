@@ -0,0 +1,133 @@
+//! Helpers for running gprepo against throwaway git repositories.
+
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use git2::{IndexAddOption, Repository, Signature, Time};
+
+/// A git repository in a fresh temporary directory, removed when dropped.
+/// The directory also has room for files outside the repository.
+pub struct TestRepo {
+    dir: PathBuf,
+    root: PathBuf,
+    commits: Cell<i64>,
+}
+
+impl TestRepo {
+    pub fn new() -> TestRepo {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "gprepo-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("repo");
+        fs::create_dir_all(&root).unwrap();
+        Repository::init(&root).unwrap();
+        TestRepo {
+            dir: dir.canonicalize().unwrap(),
+            root: root.canonicalize().unwrap(),
+            commits: Cell::new(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// A path next to the repository, for outputs that should not be bundled.
+    pub fn outside(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// The time of the next commit, so that commits sort in creation order.
+    pub fn next_commit_time(&self) -> Time {
+        self.commits.set(self.commits.get() + 1);
+        Time::new(1_600_000_000 + self.commits.get(), 0)
+    }
+
+    /// Writes a file, creating its directories. Files are backdated so that
+    /// gprepo does not take them for files modified during its run.
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> &TestRepo {
+        let path = self.root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        set_modified(&path, 1_600_000_000);
+        self
+    }
+
+    /// Sets the modification time of a file, in seconds since the epoch.
+    pub fn touch(&self, path: &str, seconds: u64) -> &TestRepo {
+        set_modified(&self.root.join(path), seconds);
+        self
+    }
+
+    /// Commits every file in the working tree as the given author.
+    pub fn commit(&self, name: &str, email: &str, message: &str) -> &TestRepo {
+        let repo = Repository::open(&self.root).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::new(name, email, &self.next_commit_time()).unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap();
+        self
+    }
+
+    /// Runs gprepo in the repository.
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.run_with_env(args, &[])
+    }
+
+    /// Runs gprepo in the repository with extra environment variables set.
+    pub fn run_with_env(&self, args: &[&str], vars: &[(&str, &str)]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_gprepo"))
+            .args(args)
+            .envs(vars.iter().copied())
+            .current_dir(&self.root)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs gprepo in the repository and returns the bundle it printed.
+    pub fn bundle(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "gprepo {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+pub fn set_modified(path: &Path, seconds: u64) {
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+        .unwrap();
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}